@@ -0,0 +1,87 @@
+//! Downloads 100 small files from a local server with the default pooled
+//! client and with connection reuse turned off, and prints how long each
+//! took and how many connections it opened. Run with `cargo bench`.
+//!
+//! The server is on localhost over plain http, so this only shows the cost
+//! of a TCP handshake per file. Against humble's cdn every new connection is
+//! a TLS handshake across the internet too, which costs far more.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use hb_api::{HBClient, HttpOptions};
+use hb_test_support::{MockServer, Route};
+
+const FILES: usize = 100;
+const FILE_SIZE: usize = 16 * 1024;
+const ROUNDS: usize = 5;
+
+fn main() {
+    let server = MockServer::start();
+    for i in 0..FILES {
+        server.route(
+            &format!("/files/{}.bin", i),
+            Route::ok(vec![b'x'; FILE_SIZE]),
+        );
+    }
+
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let reused = HttpOptions::default();
+    let not_reused = HttpOptions {
+        pool_max_idle_per_host: 0,
+        ..HttpOptions::default()
+    };
+
+    for (name, http) in &[("with reuse", reused), ("without reuse", not_reused)] {
+        let mut times = Vec::new();
+        let connections_before = server.connections();
+
+        for round in 0..ROUNDS {
+            let folder = env::temp_dir().join(format!(
+                "hb-api-bench-{}-{}-{}",
+                std::process::id(),
+                name.replace(' ', "-"),
+                round
+            ));
+
+            times.push(runtime.block_on(download_all(&server, folder.clone(), http)));
+
+            let _ = fs::remove_dir_all(&folder);
+        }
+
+        times.sort();
+
+        println!(
+            "{:>14}: {} files in {:?} median, {:?} best, {} connections per round",
+            name,
+            FILES,
+            times[ROUNDS / 2],
+            times[0],
+            (server.connections() - connections_before) / ROUNDS
+        );
+    }
+}
+
+async fn download_all(server: &MockServer, folder: PathBuf, http: &HttpOptions) -> Duration {
+    let client = HBClient::new(folder, HashMap::new(), Vec::new(), http).unwrap();
+
+    let started = Instant::now();
+
+    for i in 0..FILES {
+        client
+            .download_url(&format!("{}/files/{}.bin", server.url(), i))
+            .await
+            .unwrap();
+    }
+
+    started.elapsed()
+}
@@ -0,0 +1,46 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::types::Order;
+
+/// The most recently fetched orders, up to a fixed number of them.
+#[derive(Debug)]
+pub(crate) struct OrderCache {
+    capacity: usize,
+    // least recently used first
+    orders: Mutex<VecDeque<Order>>,
+}
+
+impl OrderCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            orders: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub(crate) fn get(&self, gamekey: &str) -> Option<Order> {
+        let mut orders = self.orders.lock().unwrap();
+
+        let index = orders.iter().position(|order| order.gamekey == gamekey)?;
+        let order = orders.remove(index)?;
+        orders.push_back(order.clone());
+
+        Some(order)
+    }
+
+    pub(crate) fn insert(&self, order: Order) {
+        let mut orders = self.orders.lock().unwrap();
+
+        orders.retain(|cached| cached.gamekey != order.gamekey);
+
+        if orders.len() >= self.capacity {
+            orders.pop_front();
+        }
+
+        orders.push_back(order);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.orders.lock().unwrap().clear();
+    }
+}
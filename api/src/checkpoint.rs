@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_std::{
+    fs::File,
+    io::{ReadExt, SeekFrom},
+    prelude::*,
+    sync::Mutex as AsyncMutex,
+    task,
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::state;
+
+pub(crate) const CHECKPOINT_FILE: &str = ".hb-downloader-verified.json";
+// how much of each end of a file the quick hash reads
+const QUICK_HASH_SAMPLE: u64 = 1024 * 1024;
+// the whole checkpoint is rewritten on each save, so a library of thousands
+// of small files would otherwise spend its time rewriting it
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Files whose hash was checked recently, kept on disk so an interrupted run
+/// can skip re-hashing them. An entry only counts while the file's size and
+/// modification time are unchanged and it's younger than `max_age`.
+#[derive(Debug)]
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    max_age: Duration,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    // whether there are entries that aren't saved yet
+    dirty: AtomicBool,
+    last_saved: Mutex<Option<Instant>>,
+    // held across each write, so an older copy never lands over a newer one
+    writing: AsyncMutex<()>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    modified: u64,
+    verified_at: u64,
+    #[serde(default)]
+    quick_hash: Option<String>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint in `folder`, starting empty if there isn't a
+    /// readable one.
+    pub(crate) fn load(folder: &Path, max_age: Duration) -> Self {
+        let path = folder.join(CHECKPOINT_FILE);
+
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            max_age,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+            last_saved: Mutex::new(None),
+            writing: AsyncMutex::new(()),
+        }
+    }
+
+    pub(crate) fn is_verified(&self, file: &Path) -> bool {
+        let entries = self.entries.lock().unwrap();
+
+        match (entries.get(file), file_stamp(file)) {
+            (Some(entry), Some((size, modified))) => {
+                entry.size == size
+                    && entry.modified == modified
+                    && now().saturating_sub(entry.verified_at) <= self.max_age.as_secs()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the start and end of `file` still hash the same as when it last
+    /// passed a full check, however long ago that was. This is only a
+    /// probabilistic check: corruption in the middle of the file goes unnoticed.
+    pub(crate) async fn quick_verified(&self, file: &Path) -> io::Result<bool> {
+        let expected = match self.entries.lock().unwrap().get(file) {
+            Some(Entry {
+                quick_hash: Some(quick_hash),
+                ..
+            }) => quick_hash.clone(),
+            _ => return Ok(false),
+        };
+
+        Ok(quick_hash(file).await? == expected)
+    }
+
+    /// Remembers that `file` just passed its hash check. The checkpoint is
+    /// saved at most every `SAVE_INTERVAL`, and when it's dropped, so a run
+    /// that's killed only hashes the last few seconds' files again.
+    pub(crate) async fn record(&self, file: &Path) {
+        let (size, modified) = match file_stamp(file) {
+            Some(stamp) => stamp,
+            None => return,
+        };
+
+        let quick_hash = match quick_hash(file).await {
+            Ok(quick_hash) => Some(quick_hash),
+            Err(e) => {
+                debug!("couldn't quick hash {}: {}", file.display(), e);
+                None
+            }
+        };
+
+        self.entries.lock().unwrap().insert(
+            file.to_path_buf(),
+            Entry {
+                size,
+                modified,
+                verified_at: now(),
+                quick_hash,
+            },
+        );
+        self.dirty.store(true, Ordering::SeqCst);
+
+        let due = self
+            .last_saved
+            .lock()
+            .unwrap()
+            .is_none_or(|last_saved| last_saved.elapsed() >= SAVE_INTERVAL);
+
+        if due {
+            self.save().await;
+        }
+    }
+
+    /// Writes the entries out on a blocking thread, holding the entries' lock
+    /// only while they're serialized.
+    async fn save(&self) {
+        let _writing = self.writing.lock().await;
+
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        *self.last_saved.lock().unwrap() = Some(Instant::now());
+
+        let contents = match serde_json::to_vec(&*self.entries.lock().unwrap()) {
+            Ok(contents) => contents,
+            Err(e) => {
+                debug!("couldn't save {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+
+        let path = self.path.clone();
+        if let Err(e) = task::spawn_blocking(move || state::write_atomic(&path, &contents)).await {
+            debug!("couldn't save {}: {}", self.path.display(), e);
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for Checkpoint {
+    /// Saves whatever was recorded since the last save, at the end of the run.
+    fn drop(&mut self) {
+        if !self.dirty.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let entries = self.entries.lock().unwrap();
+        let result = serde_json::to_vec(&*entries)
+            .map_err(io::Error::from)
+            .and_then(|contents| state::write_atomic(&self.path, &contents));
+
+        if let Err(e) = result {
+            debug!("couldn't save {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Sha1 of the file's size and its first and last `QUICK_HASH_SAMPLE` bytes.
+async fn quick_hash(file: &Path) -> io::Result<String> {
+    let mut file = File::open(file).await?;
+    let size = file.metadata().await?.len();
+
+    let mut sample = Vec::new();
+    (&mut file)
+        .take(QUICK_HASH_SAMPLE)
+        .read_to_end(&mut sample)
+        .await?;
+
+    if size > QUICK_HASH_SAMPLE {
+        let tail_start = (size - QUICK_HASH_SAMPLE).max(QUICK_HASH_SAMPLE);
+        file.seek(SeekFrom::Start(tail_start)).await?;
+        file.read_to_end(&mut sample).await?;
+    }
+
+    let mut hasher = Sha1::new();
+    hasher.update(size.to_le_bytes());
+    hasher.update(&sample);
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn file_stamp(file: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(file).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    Some((metadata.len(), modified.as_secs()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+    use crate::state::tests::TempDir;
+
+    const MAX_AGE: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn a_corrupt_file_loads_as_empty() {
+        let folder = TempDir::new();
+        let file = folder.path().join("book.epub");
+        fs::write(&file, b"book").unwrap();
+
+        task::block_on(Checkpoint::load(folder.path(), MAX_AGE).record(&file));
+        assert!(Checkpoint::load(folder.path(), MAX_AGE).is_verified(&file));
+
+        let checkpoint = folder.path().join(CHECKPOINT_FILE);
+        let mut contents = fs::read(&checkpoint).unwrap();
+        contents.truncate(contents.len() / 2);
+        fs::write(&checkpoint, contents).unwrap();
+
+        assert!(!Checkpoint::load(folder.path(), MAX_AGE).is_verified(&file));
+    }
+
+    #[test]
+    fn saves_at_most_once_an_interval_and_when_dropped() {
+        let folder = TempDir::new();
+        let first = folder.path().join("book.epub");
+        let second = folder.path().join("book.pdf");
+        fs::write(&first, b"book").unwrap();
+        fs::write(&second, b"book").unwrap();
+
+        let checkpoint = Checkpoint::load(folder.path(), MAX_AGE);
+
+        // the first is saved straight away, the second waits for the interval
+        task::block_on(checkpoint.record(&first));
+        task::block_on(checkpoint.record(&second));
+
+        let saved = Checkpoint::load(folder.path(), MAX_AGE);
+        assert!(saved.is_verified(&first));
+        assert!(!saved.is_verified(&second));
+
+        drop(checkpoint);
+
+        assert!(Checkpoint::load(folder.path(), MAX_AGE).is_verified(&second));
+    }
+}
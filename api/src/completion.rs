@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::{state, types::DownloadStruct};
+
+pub(crate) const COMPLETION_FILE: &str = ".hb-downloader-complete.json";
+
+/// Orders that were fully downloaded, kept on disk so later runs can skip
+/// them without checking each file. A marker only counts while the order's
+/// expected files are the same as when it completed.
+#[derive(Debug)]
+pub(crate) struct Completion {
+    path: PathBuf,
+    orders: Mutex<HashMap<String, Marker>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Marker {
+    completed_at: u64,
+    files: String,
+}
+
+impl Completion {
+    /// Loads the markers in `folder`, starting empty if there isn't a
+    /// readable file.
+    pub(crate) fn load(folder: &Path) -> Self {
+        let path = folder.join(COMPLETION_FILE);
+
+        let orders = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            orders: Mutex::new(orders),
+        }
+    }
+
+    pub(crate) fn is_complete(&self, gamekey: &str, files: &str) -> bool {
+        let orders = self.orders.lock().unwrap();
+
+        orders
+            .get(gamekey)
+            .map(|marker| marker.files == files)
+            .unwrap_or(false)
+    }
+
+    /// Marks the order complete and saves the markers straight away.
+    pub(crate) fn record(&self, gamekey: &str, files: String) {
+        let mut orders = self.orders.lock().unwrap();
+        orders.insert(
+            gamekey.to_owned(),
+            Marker {
+                completed_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|now| now.as_secs())
+                    .unwrap_or_default(),
+                files,
+            },
+        );
+
+        if let Err(e) = self.save(&orders) {
+            debug!("couldn't save {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self, orders: &HashMap<String, Marker>) -> io::Result<()> {
+        state::write_atomic(&self.path, &serde_json::to_vec(orders)?)
+    }
+}
+
+/// Sha1 over where each expected file goes and what it should hash to. Urls
+/// are left out since humble signs them afresh every time an order is fetched.
+pub(crate) fn file_set_hash(files: &[(PathBuf, &DownloadStruct)]) -> String {
+    let mut lines: Vec<String> = files
+        .iter()
+        .map(|(path, file)| {
+            format!(
+                "{}\t{}\t{}\t{}\n",
+                path.display(),
+                file.sha1.as_deref().unwrap_or(""),
+                file.md5.as_deref().unwrap_or(""),
+                file.file_size
+                    .map(|size| size.to_string())
+                    .unwrap_or_default()
+            )
+        })
+        .collect();
+    lines.sort();
+
+    let mut hasher = Sha1::new();
+    for line in lines {
+        hasher.update(line.as_bytes());
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::TempDir;
+
+    #[test]
+    fn markers_survive_a_reload() {
+        let folder = TempDir::new();
+
+        Completion::load(folder.path()).record("gamekey", "files".to_owned());
+
+        let completion = Completion::load(folder.path());
+        assert!(completion.is_complete("gamekey", "files"));
+        assert!(!completion.is_complete("gamekey", "other files"));
+    }
+
+    #[test]
+    fn a_corrupt_file_loads_as_empty() {
+        let folder = TempDir::new();
+        fs::write(
+            folder.path().join(COMPLETION_FILE),
+            b"{\"gamekey\": {\"compl",
+        )
+        .unwrap();
+
+        let completion = Completion::load(folder.path());
+        assert!(!completion.is_complete("gamekey", "files"));
+
+        // and is replaced by the next marker
+        completion.record("gamekey", "files".to_owned());
+        assert!(Completion::load(folder.path()).is_complete("gamekey", "files"));
+    }
+}
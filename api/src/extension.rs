@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+
+// only types that unambiguously mean one kind of file. zip is left out since
+// epubs, comics and installers are often served as plain zips
+const MIME_EXTENSIONS: &[(&str, &str)] = &[
+    ("application/epub+zip", "epub"),
+    ("application/pdf", "pdf"),
+    ("application/x-mobipocket-ebook", "mobi"),
+    ("audio/flac", "flac"),
+    ("audio/x-flac", "flac"),
+    ("audio/mpeg", "mp3"),
+    ("audio/ogg", "ogg"),
+    ("application/x-apple-diskimage", "dmg"),
+    ("application/vnd.android.package-archive", "apk"),
+    ("application/x-debian-package", "deb"),
+    ("application/x-msdownload", "exe"),
+];
+
+/// Where a file served with `content_type` should go instead of `path`, when
+/// its name lacks the extension that type implies or has a different one
+/// from the map. `None` if the name is fine or the type isn't a known one.
+pub(crate) fn corrected(path: &Path, content_type: &str) -> Option<PathBuf> {
+    let mime = content_type.split(';').next()?.trim().to_lowercase();
+
+    let extension = MIME_EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == mime)
+        .map(|(_, extension)| *extension)?;
+
+    with_extension(path, extension)
+}
+
+/// Every path `corrected` could turn `path` into, to find a file that was
+/// renamed on an earlier run.
+pub(crate) fn candidates(path: &Path) -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = MIME_EXTENSIONS
+        .iter()
+        .filter_map(|(_, extension)| with_extension(path, extension))
+        .collect();
+    candidates.dedup();
+
+    candidates
+}
+
+fn with_extension(path: &Path, extension: &str) -> Option<PathBuf> {
+    match path.extension().and_then(|current| current.to_str()) {
+        Some(current) if current.eq_ignore_ascii_case(extension) => None,
+        Some(current) if is_known(current) => Some(path.with_extension(extension)),
+        // a version number like installer-1.2 rather than an extension
+        Some(current) if current.chars().all(|c| c.is_ascii_digit()) => append(path, extension),
+        Some(_) => None,
+        None => append(path, extension),
+    }
+}
+
+fn is_known(extension: &str) -> bool {
+    MIME_EXTENSIONS
+        .iter()
+        .any(|(_, known)| known.eq_ignore_ascii_case(extension))
+}
+
+fn append(path: &Path, extension: &str) -> Option<PathBuf> {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".");
+    appended.push(extension);
+
+    Some(PathBuf::from(appended))
+}
@@ -0,0 +1,66 @@
+use std::{path::Path, process::Command};
+
+use log::info;
+
+/// Runs the post download command for a file that just passed its checks,
+/// with `{path}`, `{platform}` and `{gamekey}` filled in, quoted for the
+/// shell. A command that can't start or exits nonzero is only warned about.
+pub(crate) async fn run(command: &str, path: &Path, platform: &str, gamekey: Option<&str>) {
+    let command = command
+        .replace("{path}", &quote(&path.to_string_lossy()))
+        .replace("{platform}", &quote(platform))
+        .replace("{gamekey}", &quote(gamekey.unwrap_or("")));
+
+    let name = path.display().to_string();
+
+    // std's process api blocks, so it runs off the download threads
+    let output = async_std::task::spawn_blocking(move || shell(&command).output()).await;
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            println!("warning: post download command for {} failed: {}", name, e);
+            return;
+        }
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!("post download command for {}: {}", name, line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        info!("post download command for {}: {}", name, line);
+    }
+
+    if !output.status.success() {
+        println!(
+            "warning: post download command for {} exited with {}",
+            name, output.status
+        );
+    }
+}
+
+/// `command` run by the platform's shell, `sh -c` or `cmd /C`, for the
+/// commands users configure.
+pub fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    shell.arg(command);
+    shell
+}
+
+/// Quotes `value` so the shell passes it on as one argument, whatever is in it.
+fn quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
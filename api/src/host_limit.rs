@@ -0,0 +1,98 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_std::channel::{self, Receiver, Sender};
+use url::Url;
+
+/// Caps the connections open to each host at once, across every file and
+/// byte range downloading at the same time.
+#[derive(Debug)]
+pub(crate) struct HostLimit {
+    max: usize,
+    hosts: Mutex<HashMap<String, Slots>>,
+    /// Where links on a host were last redirected to, e.g. humble's download
+    /// links to their cdn, so the next one waits for the host it'll really
+    /// end up connected to.
+    redirects: Mutex<HashMap<String, String>>,
+}
+
+/// A channel holding one message per slot in use, so sending waits while
+/// they all are.
+#[derive(Clone, Debug)]
+struct Slots {
+    taken: Sender<()>,
+    freed: Receiver<()>,
+}
+
+/// One connection's slot for a host, given back when it's dropped.
+pub(crate) struct HostPermit {
+    host: String,
+    freed: Receiver<()>,
+}
+
+impl HostLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            hosts: Mutex::new(HashMap::new()),
+            redirects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until fewer than `max` connections are open to `url`'s host, or
+    /// to where it redirected last time.
+    pub(crate) async fn acquire(&self, url: &Url) -> HostPermit {
+        let host = host(url);
+        let host = match self.redirects.lock().unwrap().get(&host) {
+            Some(redirected) => redirected.clone(),
+            None => host,
+        };
+
+        self.acquire_host(host).await
+    }
+
+    /// Moves `permit`, taken for `url`, over to the host `url` turned out to
+    /// redirect to, if it isn't already there.
+    pub(crate) async fn redirected(&self, permit: HostPermit, url: &Url, to: &Url) -> HostPermit {
+        let to = host(to);
+
+        if permit.host == to {
+            return permit;
+        }
+
+        self.redirects.lock().unwrap().insert(host(url), to.clone());
+        drop(permit);
+
+        self.acquire_host(to).await
+    }
+
+    async fn acquire_host(&self, host: String) -> HostPermit {
+        let slots = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host.clone())
+            .or_insert_with(|| {
+                let (taken, freed) = channel::bounded(self.max);
+                Slots { taken, freed }
+            })
+            .clone();
+
+        // can't fail, the channel stays open while the map holds both ends
+        let _ = slots.taken.send(()).await;
+
+        HostPermit {
+            host,
+            freed: slots.freed,
+        }
+    }
+}
+
+fn host(url: &Url) -> String {
+    url.host_str().unwrap_or_default().to_owned()
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        let _ = self.freed.try_recv();
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    io::{self, Read},
+    sync::{mpsc, Arc},
+};
+
+use bytes::{Buf, Bytes};
+use serde::de::DeserializeOwned;
+use tokio::{sync::Semaphore, task};
+
+use crate::ApiError;
+
+// how many network chunks may be queued up waiting for the parser
+const CHUNK_BACKLOG: usize = 8;
+// how much of a body that fails to parse is kept for the error
+const SNIPPET_LEN: usize = 256;
+
+/// Deserializes a response body as it arrives instead of buffering all of it
+/// first, so only the parsed value, a few chunks and the start of the body
+/// (for the error if it doesn't parse) are held in memory.
+pub(crate) async fn from_response<T>(mut response: reqwest::Response) -> Result<T, ApiError>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let (chunks, receiver) = mpsc::channel();
+    let room = Arc::new(Semaphore::new(CHUNK_BACKLOG));
+
+    let reader = ChunkReader {
+        chunks: receiver,
+        room: room.clone(),
+        current: Bytes::new(),
+    };
+    let parser = task::spawn_blocking(move || deserialize(reader));
+
+    let mut start = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        let wanted = (SNIPPET_LEN + 1).saturating_sub(start.len());
+        start.extend_from_slice(&chunk[..wanted.min(chunk.len())]);
+
+        room.acquire().await.forget();
+
+        // the parser hung up early, its result will say why
+        if chunks.send(chunk).is_err() {
+            break;
+        }
+    }
+    drop(chunks);
+
+    parser
+        .await
+        .map_err(|e| ApiError::JsonParser(e.to_string()))?
+        .map_err(|(source, path)| error(source, path, &start))
+}
+
+/// Deserializes a whole body that's already in memory.
+pub(crate) fn from_slice<T: DeserializeOwned>(body: &[u8]) -> Result<T, ApiError> {
+    deserialize(body).map_err(|(source, path)| error(source, path, body))
+}
+
+/// Like `serde_json::from_reader`, but failures come with the path to the
+/// field that didn't match, e.g. `subproducts[3].downloads[1].platform`.
+fn deserialize<T: DeserializeOwned, R: Read>(reader: R) -> Result<T, (serde_json::Error, String)> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        (e.into_inner(), path)
+    })?;
+    deserializer.end().map_err(|e| (e, ".".to_owned()))?;
+
+    Ok(value)
+}
+
+/// Valid json in the wrong shape is reported by where it went wrong, anything
+/// else, like an html error page, by what the body looked like.
+fn error(source: serde_json::Error, path: String, body: &[u8]) -> ApiError {
+    if source.is_data() {
+        ApiError::Schema { path, source }
+    } else {
+        ApiError::Deserialize {
+            source,
+            snippet: snippet(body),
+        }
+    }
+}
+
+/// The start of a body as text, for error messages.
+fn snippet(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(&body[..body.len().min(SNIPPET_LEN)]);
+
+    if body.len() > SNIPPET_LEN {
+        format!("{}...", text)
+    } else {
+        text.into_owned()
+    }
+}
+
+/// Hands the parser, on its blocking thread, the chunks the response sends
+/// it. Each chunk taken frees a permit in `room`, which bounds how many can be
+/// queued up.
+struct ChunkReader {
+    chunks: mpsc::Receiver<Bytes>,
+    room: Arc<Semaphore>,
+    current: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.current.has_remaining() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.current = chunk;
+                    self.room.add_permits(1);
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.current.remaining());
+        buf[..len].copy_from_slice(&self.current[..len]);
+        self.current.advance(len);
+
+        Ok(len)
+    }
+}
+
+impl Drop for ChunkReader {
+    // a parser that stops early mustn't leave the response waiting for room
+    // that never frees up, the next send finds it gone instead
+    fn drop(&mut self) {
+        self.room.add_permits(CHUNK_BACKLOG);
+    }
+}
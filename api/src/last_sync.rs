@@ -0,0 +1,72 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::state;
+
+pub(crate) const LAST_SYNC_FILE: &str = ".hb-downloader-last-sync.json";
+
+/// When the last fully successful incremental sync started, kept on disk so
+/// the next one only fetches files humble updated since.
+#[derive(Debug)]
+pub(crate) struct LastSync {
+    path: PathBuf,
+    previous: Option<u64>,
+    started: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Marker {
+    last_sync: u64,
+}
+
+impl LastSync {
+    /// Loads the marker in `folder`, if there's a readable one, and notes the
+    /// time this sync started.
+    pub(crate) fn load(folder: &Path) -> Self {
+        let path = folder.join(LAST_SYNC_FILE);
+
+        let previous = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<Marker>(&contents).ok())
+            .map(|marker| marker.last_sync);
+
+        Self {
+            path,
+            previous,
+            // files humble updates while this sync runs are caught by the next
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether humble last updated the file before the previous sync, so the
+    /// copy it left behind is still current.
+    pub(crate) fn is_unchanged(&self, timestamp: Option<u64>) -> bool {
+        match (self.previous, timestamp) {
+            (Some(previous), Some(timestamp)) => timestamp < previous,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn record(&self) {
+        if let Err(e) = self.save() {
+            debug!("couldn't save {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let marker = Marker {
+            last_sync: self.started,
+        };
+
+        state::write_atomic(&self.path, &serde_json::to_vec(&marker)?)
+    }
+}
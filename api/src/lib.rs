@@ -1,27 +1,203 @@
 use std::{
     collections::{HashMap, HashSet},
-    convert::TryInto,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use async_std::{fs::File, io::copy, prelude::*};
+use async_std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, SeekFrom},
+    prelude::*,
+};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use log::{debug, info, log_enabled, trace, Level};
 use md5::Md5;
-use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+    },
+    redirect::Policy,
+    RequestBuilder, StatusCode,
+};
+use serde::Deserialize;
 use sha1::{self, Digest, Sha1};
 use thiserror::Error;
+use tokio::sync::mpsc::Sender;
 use url::Url;
 
-mod types;
+mod cache;
+mod checkpoint;
+mod completion;
+mod extension;
+mod hook;
+mod host_limit;
+mod json;
+mod last_sync;
+mod partial;
+mod progress;
+mod retry;
+mod state;
+mod template;
+pub mod types;
+
+pub use hook::shell;
+pub use progress::{PlatformTotals, Progress, ProgressEvent};
+pub use retry::RetryPolicy;
+pub use template::{FilenameTemplate, TemplateError};
+
+use cache::OrderCache;
+use checkpoint::Checkpoint;
+use completion::Completion;
+use host_limit::{HostLimit, HostPermit};
+use last_sync::LastSync;
+use partial::PartState;
+use progress::FileProgress;
+use template::Origin;
 
-use types::{Download, DownloadStruct, Order, OrderListItem};
+use types::{Download, DownloadStruct, Order, OrderListItem, Subproduct};
 
 const BASE_URL: &str = "https://www.humblebundle.com/api/v1";
+// files are hashed through a buffer this size, never read into memory whole
+const HASH_BUFFER_SIZE: usize = 256 * 1024;
+// how often a streaming download notes how much of its .part is on disk
+const PART_STATE_INTERVAL: u64 = 4 * 1024 * 1024;
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Where downloads that fail verification are moved when quarantining, inside
+/// the download folder or the platform folder they were downloading into.
+pub const QUARANTINE_FOLDER: &str = "quarantine";
+/// Where the content-addressed layout keeps each file's single copy, inside
+/// the download folder.
+pub const OBJECTS_FOLDER: &str = "objects";
+/// Where the content-addressed layout links files under their usual paths.
+pub const BY_NAME_FOLDER: &str = "by-name";
 
 pub struct HBClient {
     client: reqwest::Client,
+    base_url: String,
     headers: HeaderMap<HeaderValue>,
     download_folder: PathBuf,
-    platforms: HashSet<String>,
+    /// Where state files and the object store live, the download folder
+    /// unless files go into a session folder inside it.
+    state_folder: PathBuf,
+    previous_session: Option<PathBuf>,
+    platforms: Vec<String>,
+    cancelled: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    progress: Arc<Progress>,
+    folder_structure: FolderStructure,
+    layout: Layout,
+    check_disk_space: bool,
+    flatten_single: bool,
+    filename_template: Option<FilenameTemplate>,
+    audio_format_priority: Vec<String>,
+    ebook_format_priority: Vec<String>,
+    size_preference: Option<SizePreference>,
+    download_connections: usize,
+    host_limit: Option<HostLimit>,
+    all_platforms: bool,
+    checkpoint: Option<Checkpoint>,
+    check_file_size: bool,
+    quick_verify: bool,
+    retry: RetryPolicy,
+    retry_budget: Option<u64>,
+    retries_used: AtomicU64,
+    verify: bool,
+    workers: usize,
+    preallocate: bool,
+    platform_chain: Vec<String>,
+    keep_all_platforms: Vec<String>,
+    categories: Vec<String>,
+    post_download_command: Option<String>,
+    verify_all_hashes: bool,
+    events: Option<Sender<ProgressEvent>>,
+    tag_extensionless: bool,
+    order_cache: Option<OrderCache>,
+    byte_budget: Option<u64>,
+    quarantine: bool,
+    completion: Option<Completion>,
+    last_sync: Option<LastSync>,
+    have: HashSet<String>,
+    fix_extensions: bool,
+    platform_folders: HashMap<String, PathBuf>,
+    allowed_extensions: Vec<String>,
+    blocked_extensions: Vec<String>,
+    api_params: Vec<(String, String)>,
+    host_rewrites: HashMap<String, String>,
+}
+
+/// Which variant to keep when a download offers the same file in several
+/// sizes, such as different bitrates or resolutions.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizePreference {
+    Smallest,
+    Largest,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FolderStructure {
+    /// Every file goes straight into the download folder.
+    #[default]
+    Flat,
+    /// Files go into a subfolder named after their platform, e.g. `ebook/`.
+    ByPlatform,
+    /// Files go into `<bundle>/<subproduct>/`, named after the order's product.
+    Nested,
+    /// Files go into a folder named after the subproduct's machine name, which
+    /// unlike its display name doesn't change between orders or over time.
+    ByMachineName,
+    /// Files go into a subfolder named after their lowercased extension, e.g.
+    /// `epub/`, or `other/` if they have none.
+    ByExtension,
+}
+
+/// How downloaded files are stored. `folder_structure` decides the paths in
+/// either case.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Each file is stored at its path.
+    #[default]
+    Plain,
+    /// Each file is stored once as `objects/<xx>/<hash>`, named by the hash
+    /// humble lists and it was verified against, and hard linked from its
+    /// path under `by-name/`. The same file in several bundles shares one
+    /// copy. Files without a hash are stored at their path under `by-name/`.
+    ContentAddressed,
+}
+
+/// Tuning for the underlying HTTP client. Connections are pooled and reused
+/// across requests; these only change how that pool behaves.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HttpOptions {
+    /// Redirects to follow per request, zero turns any redirect into an error.
+    pub max_redirects: usize,
+    /// Speak HTTP/2 straight away instead of negotiating it, only works against
+    /// servers known to support it.
+    pub http2_prior_knowledge: bool,
+    /// Seconds an idle connection is kept in the pool, `None` keeps it forever.
+    pub pool_idle_timeout: Option<u64>,
+    pub pool_max_idle_per_host: usize,
+    /// Seconds between TCP keepalive probes, `None` disables them.
+    pub tcp_keepalive: Option<u64>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            max_redirects: 10,
+            http2_prior_knowledge: false,
+            pool_idle_timeout: Some(90),
+            pool_max_idle_per_host: usize::MAX,
+            tcp_keepalive: Some(60),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -30,134 +206,2429 @@ pub enum ApiError {
     Api(#[from] reqwest::Error),
     #[error("io error")]
     IO(#[from] std::io::Error),
+    #[error("error parsing humble bundle api response")]
+    Json(#[from] serde_json::Error),
     #[error("url parse error")]
     UrlParse(#[from] url::ParseError),
+    #[error("unexpected response from humble bundle api, body starts with {snippet:?}")]
+    Deserialize {
+        #[source]
+        source: serde_json::Error,
+        snippet: String,
+    },
+    #[error("json parser stopped before finishing the response: {0}")]
+    JsonParser(String),
+    #[error("humble bundle api response didn't have the expected format at {path}")]
+    Schema {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("order {gamekey} wasn't found, it may have been refunded or removed")]
+    OrderNotFound { gamekey: String },
+    #[error("humble bundle rejected the session, check the Cookie header")]
+    Unauthorized,
+    #[error("header {0} isn't valid, check it for line breaks or other control characters")]
+    InvalidHeader(String),
+    #[error("download link for {0} was rejected, it has probably expired")]
+    LinkExpired(String),
+    #[error("server ignored a byte range request")]
+    RangeIgnored,
+    #[error("server sent {written} of the {expected} bytes asked for from {start}")]
+    ShortRange {
+        start: u64,
+        expected: u64,
+        written: u64,
+    },
+    #[error("cancelled")]
+    Cancelled,
+    #[error("reached the limit of {0} bytes for this run")]
+    ByteBudgetReached(u64),
+    #[error("disk full while writing {0}, free some space and run again")]
+    DiskFull(String),
+    #[error("too many failures, aborting after using up the retry budget of {0}")]
+    RetryBudgetExhausted(u64),
+}
+
+impl ApiError {
+    /// A stable name for the kind of failure, for tools that want to tell
+    /// them apart without matching on messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::Api(_) => "http",
+            ApiError::IO(_) => "io",
+            ApiError::Json(_) => "json",
+            ApiError::Deserialize { .. } => "deserialize",
+            ApiError::Schema { .. } => "schema",
+            ApiError::JsonParser(_) => "json_parser",
+            ApiError::UrlParse(_) => "url_parse",
+            ApiError::OrderNotFound { .. } => "order_not_found",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::InvalidHeader(_) => "invalid_header",
+            ApiError::LinkExpired(_) => "link_expired",
+            ApiError::RangeIgnored => "range_ignored",
+            ApiError::ShortRange { .. } => "short_range",
+            ApiError::Cancelled => "cancelled",
+            ApiError::ByteBudgetReached(_) => "byte_budget_reached",
+            ApiError::DiskFull(_) => "disk_full",
+            ApiError::RetryBudgetExhausted(_) => "retry_budget_exhausted",
+        }
+    }
+
+    /// The file the failure happened on, when it's known.
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            ApiError::LinkExpired(file) | ApiError::DiskFull(file) => Some(file),
+            _ => None,
+        }
+    }
 }
 
 impl HBClient {
     pub fn new(
         download_folder: PathBuf,
         headers: HashMap<String, String>,
-        platforms: HashSet<String>,
+        platforms: Vec<String>,
+        http: &HttpOptions,
+    ) -> Result<Self, ApiError> {
+        let headers = header_map(&headers)?;
+
+        // reqwest's `Policy::none` hands back the 3xx response as if it were the
+        // file, so a limit of zero turns any redirect into an explicit error instead
+        let redirect = if http.max_redirects == 0 {
+            Policy::custom(|attempt| attempt.error("redirects are disabled (max_redirects = 0)"))
+        } else {
+            Policy::limited(http.max_redirects)
+        };
+
+        let mut builder = reqwest::Client::builder()
+            .redirect(redirect)
+            .pool_idle_timeout(http.pool_idle_timeout.map(Duration::from_secs))
+            .pool_max_idle_per_host(http.pool_max_idle_per_host)
+            .tcp_keepalive(http.tcp_keepalive.map(Duration::from_secs));
+
+        if http.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+
+        // with both tls features on, rustls wins
+        #[cfg(feature = "rustls-tls")]
+        {
+            builder = builder.use_rustls_tls();
+        }
+
+        let mut client = Self::with_client(builder.build()?, download_folder, platforms);
+        client.headers = headers;
+
+        Ok(client)
+    }
+
+    /// Uses a client the caller has already set up, e.g. with its own cookie
+    /// store or proxy, instead of building one from `HttpOptions`.
+    pub fn with_client(
+        client: reqwest::Client,
+        download_folder: PathBuf,
+        platforms: Vec<String>,
     ) -> Self {
-        let headers: HeaderMap = (&headers).try_into().unwrap();
         Self {
-            client: reqwest::Client::new(),
+            client,
+            base_url: BASE_URL.to_owned(),
+            state_folder: download_folder.clone(),
             download_folder,
-            headers,
+            previous_session: None,
+            headers: HeaderMap::new(),
             platforms,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Progress::default()),
+            folder_structure: FolderStructure::default(),
+            layout: Layout::default(),
+            check_disk_space: false,
+            flatten_single: false,
+            filename_template: None,
+            audio_format_priority: Vec::new(),
+            ebook_format_priority: Vec::new(),
+            size_preference: None,
+            download_connections: 1,
+            host_limit: None,
+            all_platforms: false,
+            checkpoint: None,
+            check_file_size: true,
+            quick_verify: false,
+            retry: RetryPolicy::default(),
+            retry_budget: None,
+            retries_used: AtomicU64::new(0),
+            verify: true,
+            workers: 1,
+            preallocate: false,
+            platform_chain: Vec::new(),
+            keep_all_platforms: Vec::new(),
+            categories: Vec::new(),
+            post_download_command: None,
+            verify_all_hashes: false,
+            events: None,
+            tag_extensionless: false,
+            order_cache: None,
+            byte_budget: None,
+            quarantine: false,
+            completion: None,
+            last_sync: None,
+            have: HashSet::new(),
+            fix_extensions: false,
+            platform_folders: HashMap::new(),
+            allowed_extensions: Vec::new(),
+            blocked_extensions: Vec::new(),
+            api_params: Vec::new(),
+            host_rewrites: HashMap::new(),
+        }
+    }
+
+    /// Where the humble api is, e.g. a mock server in tests, instead of
+    /// humble's own.
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// How requests that fail with a dropped connection, timeout, 429 or 5xx
+    /// are retried.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Cap on retries and resumes across every request and file, after which
+    /// the run fails with `ApiError::RetryBudgetExhausted` instead of working
+    /// through an outage one slow failure at a time.
+    pub fn retry_budget(mut self, retry_budget: Option<u64>) -> Self {
+        self.retry_budget = retry_budget;
+        self
+    }
+
+    /// Starts the retry budget over, e.g. for the next of several runs.
+    pub fn reset_retry_budget(&self) {
+        self.retries_used.store(0, Ordering::SeqCst);
+    }
+
+    pub fn folder_structure(mut self, folder_structure: FolderStructure) -> Self {
+        self.folder_structure = folder_structure;
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Downloads into the `session` folder inside the download folder, e.g.
+    /// one named after the date, to keep snapshots of the library apart. State
+    /// files and the object store stay in the download folder, shared by every
+    /// session. Files that are still valid in the `previous` session's folder
+    /// are hard linked from there instead of downloaded again.
+    pub fn session_folder(mut self, session: &str, previous: Option<&str>) -> Self {
+        self.download_folder = self.state_folder.join(session);
+        self.previous_session = previous.map(|previous| self.state_folder.join(previous));
+        self
+    }
+
+    pub fn check_disk_space(mut self, check_disk_space: bool) -> Self {
+        self.check_disk_space = check_disk_space;
+        self
+    }
+
+    /// In the nested layout, put the files of single-subproduct orders directly
+    /// in the bundle folder instead of a redundant `<bundle>/<bundle>/`.
+    pub fn flatten_single(mut self, flatten_single: bool) -> Self {
+        self.flatten_single = flatten_single;
+        self
+    }
+
+    /// Build each file's path from a template instead of the folder structure.
+    /// Downloads the template can't be filled in for fall back to the folder
+    /// structure and the file name from their url.
+    pub fn filename_template(mut self, filename_template: Option<FilenameTemplate>) -> Self {
+        self.filename_template = filename_template;
+        self
+    }
+
+    /// Audio formats in order of preference, e.g. `["flac", "mp3"]`. Only the
+    /// first one a soundtrack offers gets downloaded.
+    pub fn audio_format_priority(mut self, audio_format_priority: Vec<String>) -> Self {
+        self.audio_format_priority = audio_format_priority;
+        self
+    }
+
+    /// Ebook formats in order of preference, e.g. `["epub", "pdf"]`. Only the
+    /// first one a book offers gets downloaded.
+    pub fn ebook_format_priority(mut self, ebook_format_priority: Vec<String>) -> Self {
+        self.ebook_format_priority = ebook_format_priority;
+        self
+    }
+
+    /// Keep only the smallest or largest of the files in a download that share
+    /// a label apart from their quality, e.g. `MP3 320kbps` and `MP3 128kbps`.
+    /// `None` keeps every variant.
+    pub fn size_preference(mut self, size_preference: Option<SizePreference>) -> Self {
+        self.size_preference = size_preference;
+        self
+    }
+
+    /// Only download files with one of these extensions, e.g. `["epub",
+    /// "pdf"]`. Empty allows any.
+    pub fn allowed_extensions(mut self, allowed_extensions: Vec<String>) -> Self {
+        self.allowed_extensions = normalize_extensions(allowed_extensions);
+        self
+    }
+
+    /// Never download files with these extensions, e.g. `["torrent", "asc"]`
+    /// for pointer files and signatures listed next to the real download.
+    pub fn blocked_extensions(mut self, blocked_extensions: Vec<String>) -> Self {
+        self.blocked_extensions = normalize_extensions(blocked_extensions);
+        self
+    }
+
+    /// Connections to split each file across, for servers that rate limit per
+    /// connection. Only used when the server accepts byte ranges and the size
+    /// is known, otherwise files download over a single connection.
+    pub fn download_connections(mut self, download_connections: usize) -> Self {
+        self.download_connections = download_connections.max(1);
+        self
+    }
+
+    /// How many files of an order download at the same time.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Extend files to their full size before streaming into them, when the
+    /// size is known, to cut fragmentation and hit a full disk straight away.
+    /// Byte range downloads always preallocate.
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Cap on connections open to any one host at once, shared by every file
+    /// and byte range downloading, however many workers and orders the
+    /// caller runs. Requests over the cap wait for a connection to finish.
+    pub fn max_connections_per_host(mut self, max_connections_per_host: Option<usize>) -> Self {
+        self.host_limit = max_connections_per_host.map(HostLimit::new);
+        self
+    }
+
+    /// Download a single platform per subproduct, the first one in the chain
+    /// it offers, instead of every configured platform. E.g. `["linux",
+    /// "windows"]` gets the windows build only for games with no linux one.
+    pub fn platform_chain(mut self, platform_chain: Vec<String>) -> Self {
+        self.platform_chain = platform_chain;
+        self
+    }
+
+    /// Machine names of subproducts to download every wanted platform of,
+    /// configured or in the chain, even when `platform_chain` would pick
+    /// just one. E.g. a game played on both a linux and a windows machine.
+    pub fn keep_all_platforms(mut self, keep_all_platforms: Vec<String>) -> Self {
+        self.keep_all_platforms = keep_all_platforms;
+        self
+    }
+
+    /// Only download subproducts in one of these categories, e.g. `game`,
+    /// going by the order's category for those humble doesn't give one. Empty
+    /// downloads every subproduct.
+    pub fn categories(mut self, categories: Vec<String>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// Shell command to run after each file is downloaded and passes its
+    /// checks, e.g. to import it somewhere. `{path}`, `{platform}` and
+    /// `{gamekey}` are replaced with the file's, already quoted. Its output
+    /// is logged at info level, and it failing is only a warning.
+    pub fn post_download_command(mut self, post_download_command: Option<String>) -> Self {
+        self.post_download_command = post_download_command;
+        self
+    }
+
+    /// Add the platform to file names that have no extension, e.g.
+    /// `game-installer-linux`, so bare installers for different platforms
+    /// can't be mistaken for each other. Doesn't apply to templated paths.
+    pub fn tag_extensionless(mut self, tag_extensionless: bool) -> Self {
+        self.tag_extensionless = tag_extensionless;
+        self
+    }
+
+    /// Add or correct a file's extension when the server's Content-Type says
+    /// clearly what it is, e.g. `application/epub+zip`, so other tools
+    /// recognise it. Only a short list of unambiguous types is used.
+    pub fn fix_extensions(mut self, fix_extensions: bool) -> Self {
+        self.fix_extensions = fix_extensions;
+        self
+    }
+
+    /// Puts each platform's files under its own folder instead of the download
+    /// folder, laid out the same way, e.g. windows installers on another
+    /// drive. Relative folders are taken from the download folder.
+    pub fn platform_folders(mut self, platform_folders: HashMap<String, PathBuf>) -> Self {
+        self.platform_folders = platform_folders;
+        self
+    }
+
+    /// File names and sha1 or md5 hashes of files that are already had
+    /// elsewhere, e.g. on another machine, which are skipped instead of
+    /// downloaded.
+    pub fn have(mut self, have: HashSet<String>) -> Self {
+        self.have = have;
+        self
+    }
+
+    /// Move downloads that fail verification into `QUARANTINE_FOLDER` with a
+    /// `.bad` suffix and a note of the expected and actual hashes, instead of
+    /// deleting them.
+    pub fn quarantine_on_failure(mut self, quarantine: bool) -> Self {
+        self.quarantine = quarantine;
+        self
+    }
+
+    /// Stop starting new files once this many bytes have been downloaded,
+    /// failing with `ApiError::ByteBudgetReached`. Files already downloading
+    /// are finished, so the total can go over by up to their size.
+    pub fn byte_budget(mut self, byte_budget: Option<u64>) -> Self {
+        self.byte_budget = byte_budget;
+        self
+    }
+
+    /// Ignore the platform filter and download every platform.
+    pub fn all_platforms(mut self, all_platforms: bool) -> Self {
+        self.all_platforms = all_platforms;
+        self
+    }
+
+    /// Check hashes of existing and freshly downloaded files. When off, files
+    /// are only checked against their expected size, so a corrupted transfer
+    /// of the right length goes unnoticed.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Check files with both a sha1 and an md5 against both, failing if
+    /// either doesn't match, instead of only against the sha1.
+    pub fn verify_all_hashes(mut self, verify_all_hashes: bool) -> Self {
+        self.verify_all_hashes = verify_all_hashes;
+        self
+    }
+
+    /// Treat an existing file whose size differs from the size humble reports
+    /// as invalid, even when there's no hash to check it against.
+    pub fn check_file_size(mut self, check_file_size: bool) -> Self {
+        self.check_file_size = check_file_size;
+        self
+    }
+
+    /// Remember files that passed their hash check in a state file in the
+    /// download folder, and don't hash them again for `max_age` as long as
+    /// they're unchanged. Lets an interrupted sync pick up where it left off.
+    pub fn verify_checkpoint(mut self, max_age: Option<Duration>) -> Self {
+        self.checkpoint = max_age.map(|max_age| Checkpoint::load(&self.state_folder, max_age));
+        self
+    }
+
+    /// Remember orders that downloaded completely in a state file in the
+    /// download folder, and skip them in `download_order` while the files they
+    /// should have are unchanged.
+    pub fn skip_complete(mut self, skip_complete: bool) -> Self {
+        self.completion = if skip_complete {
+            Some(Completion::load(&self.state_folder))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Skip files humble last updated before the previous incremental sync
+    /// when a copy of them is already on disk, going by a marker in the
+    /// download folder. Files humble gives no timestamp for are checked as
+    /// usual. The marker only moves on with `record_sync`.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.last_sync = if incremental {
+            Some(LastSync::load(&self.state_folder))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Saves the time this incremental sync started as the marker the next
+    /// one compares against. Only call it once every file has downloaded.
+    pub fn record_sync(&self) {
+        if let Some(last_sync) = &self.last_sync {
+            last_sync.record();
         }
     }
 
+    /// Accept an existing file without hashing all of it if only its size,
+    /// start and end match what the checkpoint recorded at its last full check.
+    /// Much faster on large files, but corruption elsewhere in the file goes
+    /// unnoticed, so run without it now and then. Needs `verify_checkpoint`.
+    pub fn quick_verify(mut self, quick_verify: bool) -> Self {
+        self.quick_verify = quick_verify;
+        self
+    }
+
+    /// Flag that, once set, stops any in-progress hashing at the next buffer
+    /// boundary and skips remaining files with `ApiError::Cancelled`.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Flag that, while set, holds downloads between chunks until it's cleared.
+    /// Servers may drop a connection that's paused for too long, which fails
+    /// that file.
+    pub fn pause_flag(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+
+    /// Overall progress across every download this client runs.
+    pub fn progress(&self) -> Arc<Progress> {
+        self.progress.clone()
+    }
+
+    /// Send an event to `events`, from `tokio::sync::mpsc::channel`, as
+    /// each file starts, receives data, finishes or fails. Progress events are
+    /// dropped while the channel is full, their bytes counted in the next one,
+    /// so a slow receiver never holds up the data or takes more memory than
+    /// the channel's capacity. The others wait for room.
+    pub fn progress_events(mut self, events: Sender<ProgressEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
     pub async fn list_orders(&self) -> Result<Vec<OrderListItem>, ApiError> {
         let response = self
-            .client
-            .get(&*format!("{}/{}", BASE_URL, "user/order"))
-            .headers(self.headers.clone())
-            .send()
+            .send(
+                self.client
+                    .get(&*format!("{}/{}", self.base_url, "user/order"))
+                    .query(&self.api_params)
+                    .headers(self.headers.clone()),
+            )
             .await?;
+        let response = check_status(response)?;
+
+        let body = response.bytes().await?;
+
+        json::from_slice(&body)
+    }
+
+    /// Extra query parameters sent with every order list and order request,
+    /// e.g. a locale, for api behaviour that isn't otherwise configurable.
+    pub fn api_params(mut self, api_params: HashMap<String, String>) -> Self {
+        let mut api_params: Vec<_> = api_params.into_iter().collect();
+        api_params.sort();
+
+        self.api_params = api_params;
+        self
+    }
+
+    /// Fetch files from another host than the one in their download url,
+    /// keeping the path and query, e.g. a caching proxy or a mirror. Keys are
+    /// the hosts humble links to.
+    pub fn host_rewrites(mut self, host_rewrites: HashMap<String, String>) -> Self {
+        self.host_rewrites = host_rewrites;
+        self
+    }
 
-        let orders = response.json::<Vec<OrderListItem>>().await?;
+    /// Keep up to `capacity` fetched orders in memory, so fetching the same
+    /// order again, e.g. to plan and then download it, doesn't go back to
+    /// humble. 0 turns the cache off.
+    pub fn order_cache(mut self, capacity: usize) -> Self {
+        self.order_cache = if capacity > 0 {
+            Some(OrderCache::new(capacity))
+        } else {
+            None
+        };
+        self
+    }
 
-        Ok(orders)
+    pub fn clear_order_cache(&self) {
+        if let Some(cache) = &self.order_cache {
+            cache.clear();
+        }
     }
 
     pub async fn get_order(&self, gamekey: &str) -> Result<Order, ApiError> {
+        if let Some(order) = self
+            .order_cache
+            .as_ref()
+            .and_then(|cache| cache.get(gamekey))
+        {
+            return Ok(order);
+        }
+
+        self.fetch_order(gamekey).await
+    }
+
+    /// Always asks humble for the order, refreshing the cache with it.
+    async fn fetch_order(&self, gamekey: &str) -> Result<Order, ApiError> {
+        let response = self.order_response(gamekey).await?;
+
+        let order = json::from_response::<Order>(response).await?;
+
+        if let Some(cache) = &self.order_cache {
+            cache.insert(order.clone());
+        }
+
+        Ok(order)
+    }
+
+    /// The order's json exactly as humble sent it, for debugging parse errors.
+    pub async fn get_order_raw(&self, gamekey: &str) -> Result<String, ApiError> {
+        Ok(self.order_response(gamekey).await?.text().await?)
+    }
+
+    async fn order_response(&self, gamekey: &str) -> Result<reqwest::Response, ApiError> {
         let response = self
-            .client
-            .get(&*format!("{}/{}/{}", BASE_URL, "order", gamekey))
-            .headers(self.headers.clone())
-            .send()
+            .send(
+                self.client
+                    .get(&*format!("{}/{}/{}", self.base_url, "order", gamekey))
+                    .query(&self.api_params)
+                    .headers(self.headers.clone()),
+            )
             .await?;
 
-        let order = response.json::<Order>().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(ApiError::OrderNotFound {
+                gamekey: gamekey.to_owned(),
+            });
+        }
 
-        Ok(order)
+        check_status(response)
     }
 
+    /// Downloads every file in the order. Download links are signed and expire,
+    /// so if one gets rejected part way through a long run the order is fetched
+    /// again for fresh links and the download retried once.
     pub async fn download_order(&self, order: &Order) -> Result<(), ApiError> {
-        for product in &order.subproducts {
-            for download in &product.downloads {
-                self.download(download).await?;
-            }
+        // planned once, so its messages are only printed once
+        let files = self.order_files(order, None);
+
+        let completion = match &self.completion {
+            Some(completion) => completion,
+            None => return self.download_order_matching(order, None, files).await,
+        };
+
+        let planned = self.file_paths(&files);
+        let file_set = completion::file_set_hash(&planned);
+
+        if completion.is_complete(&order.gamekey, &file_set) {
+            println!(
+                "order {} was completed before and hasn't changed, skipping",
+                order.gamekey
+            );
+            return Ok(());
+        }
+
+        self.download_order_matching(order, None, files).await?;
+
+        // files that were skipped or failed their check aren't on disk
+        if planned.iter().all(|(path, _)| path.exists()) {
+            completion.record(&order.gamekey, file_set);
         }
 
         Ok(())
     }
 
-    pub async fn download(&self, download: &Download) -> Result<(), ApiError> {
-        for file in &download.download_struct {
-            if file.url.is_none() {
-                continue;
+    /// Like `download_order`, but only for the subproducts with the given
+    /// machine name or display name.
+    pub async fn download_order_subproduct(
+        &self,
+        order: &Order,
+        name: &str,
+    ) -> Result<(), ApiError> {
+        self.download_order_matching(order, Some(name), self.order_files(order, Some(name)))
+            .await
+    }
+
+    /// Downloads `files`, the order's files matching `name`, planning them
+    /// again from a fresh copy of the order if their links expired.
+    async fn download_order_matching(
+        &self,
+        order: &Order,
+        name: Option<&str>,
+        files: Vec<(PathBuf, Origin<'_>, &Download, &DownloadStruct)>,
+    ) -> Result<(), ApiError> {
+        match self.download_order_files(&files).await {
+            Err(ApiError::LinkExpired(file)) => {
+                println!(
+                    "download link for {} expired, fetching fresh links for order {}",
+                    file, order.gamekey
+                );
+
+                // a cached copy would have the same expired links
+                let order = self.fetch_order(&order.gamekey).await?;
+                self.download_order_files(&self.order_files(&order, name))
+                    .await
             }
+            result => result,
+        }
+    }
+
+    async fn download_order_files(
+        &self,
+        files: &[(PathBuf, Origin<'_>, &Download, &DownloadStruct)],
+    ) -> Result<(), ApiError> {
+        stream::iter(files)
+            .map(|(folder, origin, download, file)| {
+                self.download_file(Destination::Folder(folder), Some(*origin), download, file)
+            })
+            .buffer_unordered(self.workers)
+            .try_for_each(|_| future::ready(Ok(())))
+            .await
+    }
 
-            if !self.platforms.contains(&download.platform) {
-                continue;
+    /// Where every file of the order that a download would fetch ends up,
+    /// going by the platform filter, format priorities and folder layout.
+    /// Files left out are only logged at info level, so nothing gets mixed
+    /// into output made from the plan, e.g. `PrintUrls`.
+    pub fn planned_files<'a>(&self, order: &'a Order) -> Vec<(PathBuf, &'a DownloadStruct)> {
+        self.file_paths(&self.order_files(order, None))
+    }
+
+    /// The folders files are downloaded into: the download folder and every
+    /// platform folder.
+    pub fn download_roots(&self) -> Vec<PathBuf> {
+        let mut roots = vec![self.download_folder.clone()];
+
+        for platform_folder in self.platform_folders.values() {
+            let root = self.download_folder.join(platform_folder);
+            if !roots.contains(&root) {
+                roots.push(root);
             }
+        }
+
+        roots
+    }
+
+    /// Where each of `files` from `order_files` ends up.
+    fn file_paths<'a>(
+        &self,
+        files: &[(PathBuf, Origin<'a>, &'a Download, &'a DownloadStruct)],
+    ) -> Vec<(PathBuf, &'a DownloadStruct)> {
+        files
+            .iter()
+            .filter_map(|(folder, origin, download, file)| {
+                let url = Url::parse(&file.url.as_ref()?.web).ok()?;
+                let fname = url_file_name(&url)?;
+
+                Some((
+                    self.file_path(folder, Some(*origin), download, fname),
+                    *file,
+                ))
+            })
+            .collect()
+    }
+
+    /// The files of the order to download, with the folder each goes into.
+    fn order_files<'a>(
+        &self,
+        order: &'a Order,
+        name: Option<&str>,
+    ) -> Vec<(PathBuf, Origin<'a>, &'a Download, &'a DownloadStruct)> {
+        let mut files = Vec::new();
+        // some orders list the same file under several downloads
+        let mut seen = HashSet::new();
 
-            let download_url = Url::parse(&file.url.as_ref().unwrap().web)?;
+        let subproducts = order
+            .subproducts
+            .iter()
+            .filter(|subproduct| name.is_none_or(|name| subproduct.is_named(name)))
+            .filter(|subproduct| {
+                self.categories.is_empty()
+                    || subproduct.in_category(&order.product, &self.categories)
+            });
 
-            let mut dest = {
-                let fname = download_url
-                    .path_segments()
-                    .and_then(|segments| segments.last())
-                    .and_then(|name| if name.is_empty() { None } else { Some(name) })
-                    .unwrap();
+        for subproduct in subproducts {
+            let folder = self.subproduct_dir(order, subproduct);
+            let origin = Origin { order, subproduct };
+            let keep_all = self
+                .keep_all_platforms
+                .iter()
+                .any(|name| subproduct.machine_name.as_deref() == Some(name));
+            let chosen = if keep_all {
+                None
+            } else {
+                self.chosen_platform(subproduct)
+            };
 
-                let file_name = self.download_folder.join(fname);
+            for download in &subproduct.downloads {
+                let wanted = if self.all_platforms {
+                    true
+                } else if self.platform_chain.is_empty() {
+                    self.platforms.contains(&download.platform)
+                } else if keep_all {
+                    self.platforms.contains(&download.platform)
+                        || self.platform_chain.contains(&download.platform)
+                } else {
+                    chosen == Some(download.platform.as_str())
+                };
 
-                if file_name.exists() {
-                    let mut input = File::open(&file_name).await?;
-                    let mut content = Vec::new();
-                    input.read_to_end(&mut content).await?;
+                if !wanted {
+                    continue;
+                }
 
-                    if check_data_validity(file, &content) {
-                        println!("valid {} already exists locally, ignoring", fname);
-                        continue;
+                for file in self.preferred_files(download) {
+                    match &file.url {
+                        Some(url) if !url.web.is_empty() && !seen.insert(url.web.as_str()) => {
+                            info!(
+                                "skipping {}, already listed in this order",
+                                file.file_name().as_deref().unwrap_or(&url.web)
+                            );
+                        }
+                        _ => files.push((folder.clone(), origin, download, file)),
                     }
                 }
+            }
+        }
 
-                println!("downloading file {}", fname);
+        files
+    }
 
-                File::create(file_name).await?
-            };
+    /// Downloads the files of one download into the download folder, if its
+    /// platform is wanted.
+    pub async fn download(&self, download: &Download) -> Result<(), ApiError> {
+        let wanted = self.all_platforms
+            || self.platforms.contains(&download.platform)
+            || self.platform_chain.contains(&download.platform);
 
-            let response = self
-                .client
-                .get(download_url)
-                .headers(self.headers.clone())
-                .send()
-                .await?;
+        if !wanted {
+            return Ok(());
+        }
+
+        self.download_files(download).await
+    }
 
-            let content = response.bytes().await?;
-            let mut content = content.as_ref();
+    /// Downloads the file at `url` into the download folder whatever its
+    /// platform, with the same naming and checks as any other, e.g. to look
+    /// into one file that keeps failing.
+    pub async fn download_url(&self, url: &str) -> Result<(), ApiError> {
+        let download = Download {
+            download_struct: vec![DownloadStruct {
+                url: Some(types::Url {
+                    web: url.to_owned(),
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
 
-            if check_data_validity(file, content) {
-                copy(&mut content, &mut dest).await?;
-            }
+        self.download_files(&download).await
+    }
+
+    async fn download_files(&self, download: &Download) -> Result<(), ApiError> {
+        for file in self.preferred_files(download) {
+            self.download_file(
+                Destination::Folder(&self.download_folder),
+                None,
+                download,
+                file,
+            )
+            .await?;
         }
 
         Ok(())
     }
-}
 
-fn check_data_validity(download_struct: &DownloadStruct, content: &[u8]) -> bool {
-    if let Some(expected_hash) = &download_struct.sha1 {
-        let file_hash = format!("{:x}", Sha1::digest(&content));
+    /// Downloads a file again over whatever is at `path`, e.g. a copy that
+    /// failed verification, skipping the usual check for a valid local copy.
+    /// Returns whether a verified copy ended up at `path`, which is left as it
+    /// was if the download was skipped or failed its checks.
+    pub async fn redownload(
+        &self,
+        download: &Download,
+        file: &DownloadStruct,
+        path: &Path,
+    ) -> Result<bool, ApiError> {
+        let fetched = self
+            .download_file(Destination::Replace(path), None, download, file)
+            .await?;
 
-        if expected_hash != &file_hash {
-            println!("expected sha1 {} got {}", expected_hash, file_hash);
-            return false;
-        }
-    } else if let Some(expected_hash) = &download_struct.md5 {
-        let file_hash = format!("{:x}", Md5::digest(&content));
+        Ok(fetched == Fetched::Downloaded)
+    }
+
+    async fn download_file(
+        &self,
+        destination: Destination<'_>,
+        origin: Option<Origin<'_>>,
+        download: &Download,
+        file: &DownloadStruct,
+    ) -> Result<Fetched, ApiError> {
+        let result = match self.fetch_file(destination, origin, download, file).await {
+            // every file after this one would fail the same way
+            Err(ApiError::IO(e)) if e.kind() == std::io::ErrorKind::StorageFull => {
+                Err(ApiError::DiskFull(file.file_name().unwrap_or_default()))
+            }
+            result => result,
+        };
 
-        if expected_hash != &file_hash {
-            println!("expected md5 {} got {}", expected_hash, file_hash);
-            return false;
+        if let (Err(e), Some(events)) = (&result, &self.events) {
+            let _ = events
+                .clone()
+                .send(ProgressEvent::Error {
+                    gamekey: origin.map(|origin| origin.order.gamekey.clone()),
+                    file: file.file_name().unwrap_or_default(),
+                    message: e.to_string(),
+                })
+                .await;
         }
+
+        result
     }
 
-    // yolo
-    true
+    async fn fetch_file(
+        &self,
+        destination: Destination<'_>,
+        origin: Option<Origin<'_>>,
+        download: &Download,
+        file: &DownloadStruct,
+    ) -> Result<Fetched, ApiError> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(ApiError::Cancelled);
+        }
+
+        let web = match &file.url {
+            Some(url) if !url.web.is_empty() => &url.web,
+            // humble sometimes sends a url object with nothing in it
+            Some(_) => {
+                println!(
+                    "warning: {} download has an empty url, skipping",
+                    download.platform
+                );
+                return Ok(Fetched::Skipped);
+            }
+            None => return Ok(Fetched::Skipped),
+        };
+
+        let mut download_url = Url::parse(web)?;
+
+        let rewrite = download_url
+            .host_str()
+            .and_then(|host| self.host_rewrites.get(host));
+        if let Some(host) = rewrite {
+            download_url.set_host(Some(host))?;
+        }
+
+        let fname = match url_file_name(&download_url) {
+            Some(fname) => fname,
+            None => {
+                println!(
+                    "warning: {} has no file name to save it as, skipping",
+                    redact_url(&download_url)
+                );
+                return Ok(Fetched::Skipped);
+            }
+        };
+
+        if let Destination::Folder(_) = destination {
+            if self.is_had(fname, file) {
+                println!("skipping {}, it's in the have list", fname);
+                return Ok(Fetched::Skipped);
+            }
+        }
+
+        let file_name = match destination {
+            Destination::Folder(folder) => self.file_path(folder, origin, download, fname),
+            Destination::Replace(path) => path.to_path_buf(),
+        };
+
+        let folder = file_name
+            .parent()
+            .unwrap_or(&self.download_folder)
+            .to_path_buf();
+        fs::create_dir_all(&folder).await?;
+
+        if let Destination::Folder(_) = destination {
+            let unchanged = self
+                .last_sync
+                .as_ref()
+                .is_some_and(|last_sync| last_sync.is_unchanged(file.timestamp));
+
+            if unchanged && file_name.exists() {
+                println!("{} hasn't changed since the last sync, ignoring", fname);
+                return Ok(Fetched::Skipped);
+            }
+
+            if file_name.exists() && self.is_valid(file, &file_name).await? {
+                println!("valid {} already exists locally, ignoring", fname);
+                return Ok(Fetched::Skipped);
+            }
+
+            if let Some(previous) = self.previous_session_path(&file_name) {
+                if previous.exists() && self.is_valid(file, &previous).await? {
+                    println!(
+                        "{} hasn't changed since the last session, linking it",
+                        fname
+                    );
+                    link_object(&previous, &file_name).await?;
+                    return Ok(Fetched::Skipped);
+                }
+            }
+
+            if let Some(object) = self.object_path(file) {
+                if object.exists() && self.is_valid(file, &object).await? {
+                    println!("{} is already stored, linking it", fname);
+                    link_object(&object, &file_name).await?;
+                    return Ok(Fetched::Skipped);
+                }
+            }
+
+            if self.fix_extensions {
+                for candidate in extension::candidates(&file_name) {
+                    if candidate.exists() && self.is_valid(file, &candidate).await? {
+                        println!(
+                            "valid {} already exists locally, ignoring",
+                            candidate.display()
+                        );
+                        return Ok(Fetched::Skipped);
+                    }
+                }
+            }
+        }
+
+        if let Some(budget) = self.byte_budget {
+            if self.progress.downloaded() >= budget {
+                return Err(ApiError::ByteBudgetReached(budget));
+            }
+        }
+
+        println!("downloading file {}", fname);
+
+        let permit = self.host_permit(&download_url).await;
+
+        let response = self
+            .send(
+                self.client
+                    .get(download_url.clone())
+                    .headers(self.headers.clone()),
+            )
+            .await?;
+
+        debug!(
+            "{} resolved to {}",
+            redact_url(&download_url),
+            redact_url(response.url())
+        );
+
+        // the link redirects to a cdn, whose connections are the ones to cap
+        let permit = self
+            .redirected_permit(permit, &download_url, response.url())
+            .await;
+
+        if response.status() == StatusCode::FORBIDDEN {
+            return Err(ApiError::LinkExpired(fname.to_owned()));
+        }
+
+        let response = response.error_for_status()?;
+
+        let corrected = match destination {
+            Destination::Folder(_) if self.fix_extensions => response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .and_then(|content_type| extension::corrected(&file_name, content_type)),
+            _ => None,
+        };
+
+        let file_name = match corrected {
+            Some(corrected) => {
+                println!(
+                    "saving {} as {}, going by its content type",
+                    fname,
+                    corrected.display()
+                );
+                corrected
+            }
+            None => file_name,
+        };
+
+        let expected_size = expected_size(file, &response);
+
+        if self.check_disk_space {
+            if let Some(expected_size) = expected_size {
+                let available = fs2::available_space(&folder)?;
+
+                if expected_size > available {
+                    println!(
+                        "not enough disk space for {} ({} bytes needed, {} available), skipping",
+                        fname, expected_size, available
+                    );
+                    return Ok(Fetched::Skipped);
+                }
+            }
+        }
+
+        let file_progress = FileProgress::start(
+            &self.progress,
+            self.events.as_ref(),
+            origin.map(|origin| origin.order.gamekey.as_str()),
+            fname,
+            &download.platform,
+            expected_size,
+        )
+        .await;
+
+        // Crash consistency depends on this order: the download goes to a
+        // .part file, which is synced to disk and verified, then renamed to
+        // its real name, then the directory is synced so the rename sticks,
+        // and only then is it recorded in the checkpoint. Dying at any point
+        // leaves a .part to redo or a valid file, and the checkpoint never
+        // names a file that isn't really there.
+        let part_name = part_path(&file_name);
+
+        let valid = match expected_size {
+            Some(size)
+                if self.download_connections > 1 && size > 0 && accepts_ranges(&response) =>
+            {
+                let url = response.url().clone();
+                // the ranged requests replace this one, each with its own slot
+                drop(response);
+                drop(permit);
+
+                self.download_ranges(
+                    &url,
+                    &part_name,
+                    size,
+                    self.download_connections,
+                    &file_progress,
+                )
+                .await?;
+                File::open(&part_name).await?.sync_all().await?;
+
+                !self.verify
+                    || check_file_validity(
+                        file,
+                        &part_name,
+                        self.verify_all_hashes,
+                        &self.cancelled,
+                    )
+                    .await?
+            }
+            _ => {
+                let valid = self
+                    .download_stream(response, file, &part_name, expected_size, &file_progress)
+                    .await?;
+                drop(permit);
+
+                valid
+            }
+        };
+
+        file_progress.done(valid).await;
+        // the .part is finished with, whatever happens to it next
+        PartState::remove(&part_name).await?;
+
+        if !valid && self.quarantine {
+            self.quarantine_file(file, &part_name, &file_name).await?;
+            return Ok(Fetched::Invalid);
+        } else if !valid {
+            println!("removing invalid download {}", fname);
+            fs::remove_file(&part_name).await?;
+            return Ok(Fetched::Invalid);
+        }
+
+        match self.object_path(file) {
+            Some(object) => {
+                self.store_object(&part_name, &object).await?;
+                link_object(&object, &file_name).await?;
+            }
+            None => fs::rename(&part_name, &file_name).await?,
+        }
+        sync_dir(&folder)?;
+
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.record(&file_name).await;
+        }
+
+        if let Some(command) = &self.post_download_command {
+            let gamekey = origin.map(|origin| origin.order.gamekey.as_str());
+            hook::run(command, &file_name, &download.platform, gamekey).await;
+        }
+
+        Ok(Fetched::Downloaded)
+    }
+
+    fn is_had(&self, fname: &str, file: &DownloadStruct) -> bool {
+        let had = |hash: &Option<String>| {
+            hash.as_ref()
+                .map(|hash| self.have.contains(&hash.trim().to_lowercase()))
+                .unwrap_or(false)
+        };
+
+        self.have.contains(fname) || had(&file.sha1) || had(&file.md5)
+    }
+
+    /// Moves a download that failed verification to where `file_name` would
+    /// be under the quarantine folder of the root it's in, the download folder
+    /// or a platform folder, next to a `.txt` saying what was expected of it.
+    async fn quarantine_file(
+        &self,
+        file: &DownloadStruct,
+        download: &Path,
+        file_name: &Path,
+    ) -> Result<(), ApiError> {
+        let root = self
+            .download_roots()
+            .into_iter()
+            .filter(|root| file_name.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .unwrap_or_else(|| self.download_folder.clone());
+
+        let relative = match file_name.strip_prefix(&root) {
+            Ok(relative) => relative,
+            Err(_) => Path::new(file_name.file_name().unwrap_or_default()),
+        };
+
+        let mut bad = root.join(QUARANTINE_FOLDER).join(relative).into_os_string();
+        bad.push(".bad");
+        let bad = PathBuf::from(bad);
+
+        if let Some(folder) = bad.parent() {
+            fs::create_dir_all(folder).await?;
+        }
+
+        let mut note = String::new();
+
+        if let Some(url) = &file.url {
+            note.push_str(&format!("url {}\n", url.web));
+        }
+
+        if let Some(expected) = file.file_size {
+            note.push_str(&format!("expected size {}\n", expected));
+        }
+        note.push_str(&format!(
+            "actual size {}\n",
+            fs::metadata(download).await?.len()
+        ));
+
+        if let Some(expected) = &file.sha1 {
+            let actual = sha1_digest(File::open(download).await?, &self.cancelled).await?;
+            note.push_str(&format!(
+                "expected sha1 {}\nactual sha1 {}\n",
+                expected, actual
+            ));
+        } else if let Some(expected) = &file.md5 {
+            let actual = md5_digest(File::open(download).await?, &self.cancelled).await?;
+            note.push_str(&format!(
+                "expected md5 {}\nactual md5 {}\n",
+                expected, actual
+            ));
+        }
+
+        move_file(download, &bad).await?;
+
+        let mut note_path = bad.clone().into_os_string();
+        note_path.push(".txt");
+        fs::write(note_path, note).await?;
+
+        println!(
+            "moved invalid download {} to {}",
+            file_name.display(),
+            bad.display()
+        );
+
+        Ok(())
+    }
+
+    /// Checks a file that's already on disk, wherever it came from, against
+    /// the hash humble lists for it. Files without a hash always pass.
+    pub async fn verify_file(&self, file: &DownloadStruct, path: &Path) -> Result<bool, ApiError> {
+        check_file_validity(file, path, self.verify_all_hashes, &self.cancelled).await
+    }
+
+    /// Checks a file that's already in the download folder the way a download
+    /// would before skipping it, by size, hash and the verify checkpoint.
+    pub async fn check_existing(
+        &self,
+        file: &DownloadStruct,
+        path: &Path,
+    ) -> Result<bool, ApiError> {
+        self.is_valid(file, path).await
+    }
+
+    /// Checks a file on disk against its size and hash, skipping the hashing
+    /// if the checkpoint says it was verified recently.
+    async fn is_valid(&self, file: &DownloadStruct, file_name: &Path) -> Result<bool, ApiError> {
+        if let (true, Some(expected_size)) = (self.check_file_size, file.file_size) {
+            let size = fs::metadata(file_name).await?.len();
+
+            if size != expected_size {
+                println!("expected size {} got {}", expected_size, size);
+                return Ok(false);
+            }
+        }
+
+        if !self.verify {
+            return Ok(true);
+        }
+
+        if let Some(checkpoint) = &self.checkpoint {
+            if checkpoint.is_verified(file_name) {
+                debug!(
+                    "{} verified recently, not hashing again",
+                    file_name.display()
+                );
+                return Ok(true);
+            }
+
+            if self.quick_verify && checkpoint.quick_verified(file_name).await? {
+                debug!("{} passed the quick check", file_name.display());
+                return Ok(true);
+            }
+        }
+
+        let valid =
+            check_file_validity(file, file_name, self.verify_all_hashes, &self.cancelled).await?;
+
+        if let (true, Some(checkpoint)) = (valid, &self.checkpoint) {
+            checkpoint.record(file_name).await;
+        }
+
+        Ok(valid)
+    }
+
+    /// Streams the response into the file, hashing it on the way, carrying
+    /// on from a partial file an earlier run left where it can. Only the chunk
+    /// in flight is held in memory, whatever the file size. If the connection
+    /// drops, or closes before the expected size arrived, the rest is
+    /// requested with a Range header, up to the retry limit.
+    async fn download_stream(
+        &self,
+        response: reqwest::Response,
+        file: &DownloadStruct,
+        file_name: &Path,
+        expected_size: Option<u64>,
+        file_progress: &FileProgress<'_>,
+    ) -> Result<bool, ApiError> {
+        let (mut dest, mut written, mut response) = self
+            .open_destination(response, file, file_name, expected_size)
+            .await?;
+
+        let mut hasher = if self.verify {
+            StreamHasher::new(file, self.verify_all_hashes)
+        } else {
+            StreamHasher::Unchecked
+        };
+
+        if written > 0 {
+            // the hashes are of the whole file, so they start with what's
+            // already there
+            let existing = File::open(file_name).await?.take(written);
+            hasher.update_from(existing, &self.cancelled).await?;
+            file_progress.resumed(written);
+        }
+
+        let url = response.url().clone();
+        let resumable = written > 0 || accepts_ranges(&response);
+        let mut resumes = 0;
+
+        let mut state = PartState::new(file, expected_size);
+        let mut unsaved = 0;
+
+        loop {
+            self.wait_while_paused().await?;
+
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) if expected_size.is_none_or(|size| written >= size) => break,
+                result => {
+                    if !resumable || resumes >= self.retry.retries {
+                        match result {
+                            Err(e) => return Err(e.into()),
+                            // left to the size and hash checks
+                            _ => break,
+                        }
+                    }
+
+                    self.spend_retry()?;
+
+                    let delay = self.retry.delay(resumes);
+                    match result {
+                        Err(e) => println!(
+                            "{} after {} bytes of {}, resuming in {:.1}s",
+                            e,
+                            written,
+                            file_name.display(),
+                            delay.as_secs_f32()
+                        ),
+                        _ => println!(
+                            "connection closed after {} bytes of {}, resuming in {:.1}s",
+                            written,
+                            file_name.display(),
+                            delay.as_secs_f32()
+                        ),
+                    }
+
+                    async_std::task::sleep(delay).await;
+                    resumes += 1;
+
+                    response = self.resume(&url, written).await?;
+                    continue;
+                }
+            };
+
+            hasher.update(&chunk);
+            // async-std hands the write to a blocking thread and holds the
+            // file until it's done, so no more than this chunk waits for a
+            // slow disk before the next one is read off the connection
+            dest.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+            file_progress.chunk(chunk.len() as u64);
+
+            unsaved += chunk.len() as u64;
+            if unsaved >= PART_STATE_INTERVAL {
+                // only what's synced counts, so a crash can't leave the state
+                // claiming bytes that never reached the disk
+                dest.flush().await?;
+                dest.sync_data().await?;
+                state.written = written;
+                state.save(file_name).await?;
+                unsaved = 0;
+            }
+        }
+
+        dest.flush().await?;
+
+        // don't leave preallocated space past the end of a short download
+        if self.preallocate && expected_size.is_some() && expected_size != Some(written) {
+            dest.set_len(written).await?;
+        }
+
+        dest.sync_all().await?;
+
+        if !self.verify {
+            return Ok(expected_size.is_none() || expected_size == Some(written));
+        }
+
+        Ok(hasher.matches(file))
+    }
+
+    /// Opens the file a response gets streamed into, along with the offset the
+    /// stream starts writing at and the response to stream from. A partial
+    /// file an earlier run left is appended to when it was started against
+    /// the same version of the file and the server sends the rest of it,
+    /// otherwise the file is written from the start.
+    async fn open_destination(
+        &self,
+        response: reqwest::Response,
+        file: &DownloadStruct,
+        file_name: &Path,
+        expected_size: Option<u64>,
+    ) -> Result<(File, u64, reqwest::Response), ApiError> {
+        let state = PartState::new(file, expected_size);
+
+        let existing = match fs::metadata(file_name).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+
+        let saved = match existing {
+            0 => None,
+            _ => PartState::load(file_name).await,
+        };
+        let saved = match saved {
+            Some(saved) if saved.same_file(&state) => Some(saved),
+            _ if existing > 0 => {
+                println!(
+                    "discarding {}, it was started against a different version of the file",
+                    file_name.display()
+                );
+                None
+            }
+            _ => None,
+        };
+
+        // what was synced is known to be there. Failing that, the length says
+        // how much was written, unless the file was preallocated to full size
+        let offset = match &saved {
+            Some(saved) if saved.written > 0 => saved.written.min(existing),
+            Some(_) if !self.preallocate => existing,
+            _ => 0,
+        };
+
+        let partial = offset > 0
+            && expected_size.is_some_and(|size| offset < size)
+            && accepts_ranges(&response);
+
+        let response = if partial {
+            let url = response.url().clone();
+            // only the rest of the file is wanted from here
+            drop(response);
+
+            match self.resume(&url, offset).await {
+                Ok(rest) => {
+                    println!("continuing {} from {} bytes", file_name.display(), offset);
+
+                    // anything past the offset may not have reached the disk
+                    let dest = OpenOptions::new().append(true).open(file_name).await?;
+                    dest.set_len(offset).await?;
+                    return Ok((dest, offset, rest));
+                }
+                Err(ApiError::RangeIgnored) => {
+                    println!(
+                        "server ignored the range request for {}, starting it over",
+                        file_name.display()
+                    );
+
+                    self.send(self.client.get(url).headers(self.headers.clone()))
+                        .await?
+                        .error_for_status()?
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            response
+        };
+
+        // so a crash before the new state is saved can't leave the old one
+        // vouching for the new file's bytes
+        PartState::remove(file_name).await?;
+        let dest = File::create(file_name).await?;
+        state.save(file_name).await?;
+
+        if let (true, Some(size)) = (self.preallocate, expected_size) {
+            dest.set_len(size).await?;
+        }
+
+        Ok((dest, 0, response))
+    }
+
+    /// A slot for one more connection to `url`'s host, once there's one free,
+    /// if connections per host are capped.
+    async fn host_permit(&self, url: &Url) -> Option<HostPermit> {
+        match &self.host_limit {
+            Some(host_limit) => Some(host_limit.acquire(url).await),
+            None => None,
+        }
+    }
+
+    /// Moves `permit` over to the host `url` was redirected to, if it was.
+    async fn redirected_permit(
+        &self,
+        permit: Option<HostPermit>,
+        url: &Url,
+        to: &Url,
+    ) -> Option<HostPermit> {
+        match (&self.host_limit, permit) {
+            (Some(host_limit), Some(permit)) => Some(host_limit.redirected(permit, url, to).await),
+            _ => None,
+        }
+    }
+
+    /// Requests the rest of a file from `offset` on, after its stream broke or
+    /// to finish a partial one.
+    async fn resume(&self, url: &Url, offset: u64) -> Result<reqwest::Response, ApiError> {
+        let response = self
+            .send(
+                self.client
+                    .get(url.clone())
+                    .headers(self.headers.clone())
+                    .header(RANGE, format!("bytes={}-", offset)),
+            )
+            .await?;
+
+        let response = response.error_for_status()?;
+
+        if range_ignored(&response, offset) {
+            return Err(ApiError::RangeIgnored);
+        }
+
+        Ok(response)
+    }
+
+    /// Splits the file into `connections` byte ranges and fetches them
+    /// concurrently, each writing at its own offset into a preallocated file.
+    async fn download_ranges(
+        &self,
+        url: &Url,
+        file_name: &Path,
+        size: u64,
+        connections: usize,
+        file_progress: &FileProgress<'_>,
+    ) -> Result<(), ApiError> {
+        // ranged downloads are never carried on, see `open_destination`
+        PartState::remove(file_name).await?;
+        File::create(file_name).await?.set_len(size).await?;
+
+        let connections = connections as u64;
+        let range_size = size.div_ceil(connections);
+
+        let ranges = (0..connections)
+            .map(|i| (i * range_size, ((i + 1) * range_size).min(size)))
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| self.download_range(url, file_name, start, end - 1, file_progress));
+
+        future::try_join_all(ranges).await?;
+
+        Ok(())
+    }
+
+    async fn download_range(
+        &self,
+        url: &Url,
+        file_name: &Path,
+        start: u64,
+        end: u64,
+        file_progress: &FileProgress<'_>,
+    ) -> Result<(), ApiError> {
+        let _permit = self.host_permit(url).await;
+
+        let response = self
+            .send(
+                self.client
+                    .get(url.clone())
+                    .headers(self.headers.clone())
+                    .header(RANGE, format!("bytes={}-{}", start, end)),
+            )
+            .await?;
+
+        let mut response = response.error_for_status()?;
+
+        if range_ignored(&response, start) {
+            return Err(ApiError::RangeIgnored);
+        }
+
+        let mut dest = OpenOptions::new().write(true).open(file_name).await?;
+        dest.seek(SeekFrom::Start(start)).await?;
+        let mut written = 0;
+
+        loop {
+            self.wait_while_paused().await?;
+
+            let chunk = match response.chunk().await? {
+                Some(chunk) => chunk,
+                None => break,
+            };
+
+            dest.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+            file_progress.chunk(chunk.len() as u64);
+        }
+
+        dest.flush().await?;
+
+        // the file was preallocated, so a short range would leave a hole of
+        // zeros that only a hash check could catch
+        let expected = end - start + 1;
+        if written != expected {
+            return Err(ApiError::ShortRange {
+                start,
+                expected,
+                written,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Sends the request, retrying it with backoff while it fails in a way
+    /// that might go away by itself.
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, ApiError> {
+        let mut attempt = 0;
+
+        loop {
+            let current = match request.try_clone() {
+                Some(current) if attempt < self.retry.retries => current,
+                _ => return Ok(self.execute(request).await?),
+            };
+
+            let result = self.execute(current).await;
+
+            if !retry::should_retry(&result) {
+                return Ok(result?);
+            }
+
+            self.spend_retry()?;
+
+            let delay = self.retry.delay(attempt);
+            match &result {
+                Ok(response) => println!(
+                    "got {} from {}, retrying in {:.1}s",
+                    response.status(),
+                    redact_url(response.url()),
+                    delay.as_secs_f32()
+                ),
+                Err(e) => println!(
+                    "{}, retrying in {:.1}s",
+                    redact_error(e),
+                    delay.as_secs_f32()
+                ),
+            }
+
+            async_std::task::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Counts a retry against the run's budget, failing once it's used up.
+    fn spend_retry(&self) -> Result<(), ApiError> {
+        let used = self.retries_used.fetch_add(1, Ordering::SeqCst) + 1;
+
+        match self.retry_budget {
+            Some(budget) if used > budget => Err(ApiError::RetryBudgetExhausted(budget)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends one request, logging it and the response at trace level with
+    /// secrets redacted.
+    async fn execute(&self, request: RequestBuilder) -> Result<reqwest::Response, reqwest::Error> {
+        let request = request.build()?;
+
+        if log_enabled!(Level::Trace) {
+            trace!(
+                "{} {} {}",
+                request.method(),
+                redact_url(request.url()),
+                redact_headers(request.headers())
+            );
+        }
+
+        let result = self.client.execute(request).await;
+
+        if log_enabled!(Level::Trace) {
+            match &result {
+                Ok(response) => trace!(
+                    "{} from {} {}",
+                    response.status(),
+                    redact_url(response.url()),
+                    redact_headers(response.headers())
+                ),
+                Err(e) => trace!("request failed: {}", redact_error(e)),
+            }
+        }
+
+        result
+    }
+
+    async fn wait_while_paused(&self) -> Result<(), ApiError> {
+        while self.paused.load(Ordering::SeqCst) {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return Err(ApiError::Cancelled);
+            }
+
+            async_std::task::sleep(PAUSE_POLL_INTERVAL).await;
+        }
+
+        Ok(())
+    }
+
+    /// Soundtracks and books offer every format as a separate file, so when a
+    /// priority is configured only the first format on it that's offered is kept.
+    fn preferred_files<'a>(&self, download: &'a Download) -> Vec<&'a DownloadStruct> {
+        let files = self.preferred_formats(download);
+
+        let files = match self.size_preference {
+            Some(preference) => preferred_sizes(files, preference),
+            None => files,
+        };
+
+        if self.allowed_extensions.is_empty() && self.blocked_extensions.is_empty() {
+            return files;
+        }
+
+        files
+            .into_iter()
+            .filter(|file| {
+                let name = file.file_name().unwrap_or_default();
+                let extension = Path::new(&name)
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+
+                let allowed = (self.allowed_extensions.is_empty()
+                    || self.allowed_extensions.contains(&extension))
+                    && !self.blocked_extensions.contains(&extension);
+
+                if !allowed {
+                    info!("skipping {}, its extension isn't wanted", name);
+                }
+
+                allowed
+            })
+            .collect()
+    }
+
+    fn preferred_formats<'a>(&self, download: &'a Download) -> Vec<&'a DownloadStruct> {
+        let files = &download.download_struct;
+
+        let priority = match download.platform.as_str() {
+            "audio" => &self.audio_format_priority,
+            "ebook" => &self.ebook_format_priority,
+            _ => return files.iter().collect(),
+        };
+
+        if files.len() < 2 {
+            return files.iter().collect();
+        }
+
+        for format in priority {
+            let matching: Vec<_> = files.iter().filter(|file| file.is_format(format)).collect();
+
+            if !matching.is_empty() {
+                for skipped in files.iter().filter(|file| !file.is_format(format)) {
+                    info!(
+                        "skipping {}, preferring {}",
+                        skipped.name.as_deref().unwrap_or("file"),
+                        format
+                    );
+                }
+
+                return matching;
+            }
+        }
+
+        files.iter().collect()
+    }
+
+    fn chosen_platform<'a>(&'a self, subproduct: &Subproduct) -> Option<&'a str> {
+        let chosen = self.platform_chain.iter().find(|platform| {
+            subproduct
+                .downloads
+                .iter()
+                .any(|download| &download.platform == *platform)
+        })?;
+
+        if Some(chosen) != self.platform_chain.first() {
+            info!(
+                "no {} download for {}, falling back to {}",
+                self.platform_chain[0], subproduct.human_name, chosen
+            );
+        }
+
+        Some(chosen)
+    }
+
+    fn file_path(
+        &self,
+        folder: &Path,
+        origin: Option<Origin>,
+        download: &Download,
+        fname: &str,
+    ) -> PathBuf {
+        let path = match self
+            .filename_template
+            .as_ref()
+            .and_then(|template| template.render(origin, download, fname))
+        {
+            Some(path) => self.download_folder.join(path),
+            None => self
+                .download_dir(folder, download, fname)
+                .join(self.local_file_name(fname, download)),
+        };
+
+        let path = match (self.layout, path.strip_prefix(&self.download_folder)) {
+            (Layout::ContentAddressed, Ok(relative)) => {
+                self.download_folder.join(BY_NAME_FOLDER).join(relative)
+            }
+            _ => path,
+        };
+
+        match (
+            self.platform_folders.get(&download.platform),
+            path.strip_prefix(&self.download_folder),
+        ) {
+            (Some(platform_folder), Ok(relative)) => {
+                self.download_folder.join(platform_folder).join(relative)
+            }
+            _ => path,
+        }
+    }
+
+    /// Where the content-addressed layout keeps the file, if it's in use and
+    /// humble lists a hash for the file.
+    fn object_path(&self, file: &DownloadStruct) -> Option<PathBuf> {
+        if self.layout != Layout::ContentAddressed {
+            return None;
+        }
+
+        let hash = file
+            .sha1
+            .as_deref()
+            .or(file.md5.as_deref())?
+            .trim()
+            .to_lowercase();
+
+        // it becomes part of a path, so nothing but hex
+        if hash.len() < 2 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        Some(
+            self.state_folder
+                .join(OBJECTS_FOLDER)
+                .join(&hash[..2])
+                .join(&hash),
+        )
+    }
+
+    /// Where `file_name` was in the previous session, if there was one and the
+    /// file is inside this session's folder.
+    fn previous_session_path(&self, file_name: &Path) -> Option<PathBuf> {
+        let relative = file_name.strip_prefix(&self.download_folder).ok()?;
+
+        Some(self.previous_session.as_ref()?.join(relative))
+    }
+
+    /// Moves a verified download into the object store, replacing a copy
+    /// that's there already since it matched the same hash or is corrupt.
+    async fn store_object(&self, download: &Path, object: &Path) -> Result<(), ApiError> {
+        let folder = object.parent().unwrap_or(&self.download_folder);
+        fs::create_dir_all(folder).await?;
+
+        fs::rename(download, object).await?;
+        sync_dir(folder)?;
+
+        Ok(())
+    }
+
+    fn local_file_name(&self, fname: &str, download: &Download) -> String {
+        if self.tag_extensionless
+            && Path::new(fname).extension().is_none()
+            && !download.platform.is_empty()
+        {
+            format!("{}-{}", fname, sanitize_file_name(&download.platform))
+        } else {
+            fname.to_owned()
+        }
+    }
+
+    fn subproduct_dir(&self, order: &Order, subproduct: &Subproduct) -> PathBuf {
+        match self.folder_structure {
+            FolderStructure::Nested => {
+                let bundle = self
+                    .download_folder
+                    .join(sanitize_file_name(&order.product.human_name));
+
+                if self.flatten_single && order.subproducts.len() == 1 {
+                    bundle
+                } else {
+                    bundle.join(sanitize_file_name(&subproduct.human_name))
+                }
+            }
+            FolderStructure::ByMachineName => self.download_folder.join(sanitize_file_name(
+                subproduct
+                    .machine_name
+                    .as_deref()
+                    .unwrap_or(&subproduct.human_name),
+            )),
+            FolderStructure::Flat | FolderStructure::ByPlatform | FolderStructure::ByExtension => {
+                self.download_folder.clone()
+            }
+        }
+    }
+
+    fn download_dir(&self, folder: &Path, download: &Download, fname: &str) -> PathBuf {
+        match self.folder_structure {
+            FolderStructure::ByPlatform => folder.join(sanitize_file_name(&download.platform)),
+            FolderStructure::ByExtension => match Path::new(fname).extension() {
+                Some(extension) => folder.join(sanitize_file_name(
+                    &extension.to_string_lossy().to_lowercase(),
+                )),
+                None => folder.join("other"),
+            },
+            FolderStructure::Flat | FolderStructure::Nested | FolderStructure::ByMachineName => {
+                folder.to_path_buf()
+            }
+        }
+    }
+}
+
+/// The headers sent with every request. Values are never put in the error,
+/// since they're usually secrets like the session cookie.
+fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap, ApiError> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let invalid = || ApiError::InvalidHeader(name.clone());
+
+            Ok((
+                HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?,
+                HeaderValue::from_str(value).map_err(|_| invalid())?,
+            ))
+        })
+        .collect()
+}
+
+/// Lowercases extensions and drops any leading dot, so `.PDF` matches `pdf`.
+fn normalize_extensions(extensions: Vec<String>) -> Vec<String> {
+    extensions
+        .into_iter()
+        .map(|extension| extension.trim_start_matches('.').to_lowercase())
+        .collect()
+}
+
+/// Drops all but the preferred size among files sharing a base name. Files
+/// without a label or a size are always kept.
+fn preferred_sizes(
+    files: Vec<&DownloadStruct>,
+    preference: SizePreference,
+) -> Vec<&DownloadStruct> {
+    let mut chosen: HashMap<String, &DownloadStruct> = HashMap::new();
+
+    for file in &files {
+        if let (Some(base_name), Some(size)) = (file.base_name(), file.file_size) {
+            let best = chosen.entry(base_name).or_insert(file);
+            let best_size = best.file_size.unwrap_or(size);
+
+            let better = match preference {
+                SizePreference::Smallest => size < best_size,
+                SizePreference::Largest => size > best_size,
+            };
+
+            if better {
+                *best = file;
+            }
+        }
+    }
+
+    files
+        .into_iter()
+        .filter(|file| match (file.base_name(), file.file_size) {
+            (Some(base_name), Some(_)) => {
+                let keep = std::ptr::eq(chosen[&base_name], *file);
+
+                if !keep {
+                    info!(
+                        "skipping {}, preferring the {} variant",
+                        file.name.as_deref().unwrap_or("file"),
+                        match preference {
+                            SizePreference::Smallest => "smallest",
+                            SizePreference::Largest => "largest",
+                        }
+                    );
+                }
+
+                keep
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+/// The last path segment of a download url, which is what files are saved as.
+pub(crate) fn url_file_name(url: &Url) -> Option<&str> {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|name| if name.is_empty() { None } else { Some(name) })
+}
+
+/// Replaces characters that aren't allowed in file names on common filesystems,
+/// and names like `..` that would point somewhere else.
+fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+
+    match name.as_str() {
+        "." | ".." => "_".to_owned(),
+        _ => name,
+    }
+}
+
+/// The url without its query, which holds the signature on download links.
+fn redact_url(url: &Url) -> String {
+    match url.query() {
+        Some(_) => format!("{}?<redacted>", &url[..url::Position::AfterPath]),
+        None => url.to_string(),
+    }
+}
+
+/// The error's message with the url in it redacted.
+fn redact_error(error: &reqwest::Error) -> String {
+    match error.url() {
+        Some(url) => error.to_string().replace(url.as_str(), &redact_url(url)),
+        None => error.to_string(),
+    }
+}
+
+fn redact_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match name.as_str() {
+                "cookie" | "set-cookie" | "authorization" | "proxy-authorization" => "<redacted>",
+                _ => value.to_str().unwrap_or("<binary>"),
+            };
+
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Points `file_name` at `object`, replacing whatever is there. Hard links
+/// can't cross filesystems, e.g. into a platform folder on another drive, so
+/// those get a copy instead.
+async fn link_object(object: &Path, file_name: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(file_name).await.is_ok() {
+        fs::remove_file(file_name).await?;
+    }
+
+    if fs::hard_link(object, file_name).await.is_err() {
+        fs::copy(object, file_name).await?;
+    }
+
+    Ok(())
+}
+
+/// Renames `from` to `to`, or copies it and removes the original when they're
+/// on different filesystems, which a rename can't cross.
+async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to).await?;
+            fs::remove_file(from).await
+        }
+        result => result,
+    }
+}
+
+/// Where a file is downloaded to before it's verified and renamed into place.
+fn part_path(file_name: &Path) -> PathBuf {
+    let mut part = file_name.as_os_str().to_owned();
+    part.push(".part");
+
+    PathBuf::from(part)
+}
+
+/// Makes renames in `folder` durable. Windows can't open a folder as a file,
+/// and doesn't need to.
+fn sync_dir(folder: &Path) -> std::io::Result<()> {
+    if cfg!(unix) {
+        std::fs::File::open(folder)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// How big a download should end up, from humble's listing or else the
+/// response's Content-Length. Everything that uses the size copes with
+/// `None`: progress only counts bytes, and the disk space check, preallocation,
+/// ranged downloads and size check are skipped.
+fn expected_size(file: &DownloadStruct, response: &reqwest::Response) -> Option<u64> {
+    file.file_size.or_else(|| response.content_length())
+}
+
+fn accepts_ranges(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(ACCEPT_RANGES)
+        .map(|value| value == "bytes")
+        .unwrap_or(false)
+}
+
+/// Whether a response to a request for bytes from `offset` on isn't those
+/// bytes, either the whole file or a range starting somewhere else.
+fn range_ignored(response: &reqwest::Response, offset: u64) -> bool {
+    let starts_at = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(content_range_start);
+
+    response.status() != StatusCode::PARTIAL_CONTENT
+        || starts_at.is_some_and(|start| start != offset)
+}
+
+/// Where a `Content-Range: bytes <start>-<end>/<size>` starts.
+fn content_range_start(value: &str) -> Option<u64> {
+    let (start, _) = value.trim().strip_prefix("bytes ")?.split_once('-')?;
+
+    start.parse().ok()
+}
+
+fn check_status(response: reqwest::Response) -> Result<reqwest::Response, ApiError> {
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ApiError::Unauthorized),
+        _ => Ok(response.error_for_status()?),
+    }
+}
+
+/// Drops subproducts that also appear in a newer order, matched by
+/// `machine_name`, so each title is only downloaded once. Returns how many were
+/// dropped. Subproducts without a `machine_name` are always kept.
+pub fn keep_newest_subproducts(orders: &mut [Order]) -> usize {
+    let mut newest_first: Vec<_> = orders.iter_mut().collect();
+    // created timestamps are ISO 8601, so they sort as strings
+    newest_first.sort_by(|a, b| b.created.cmp(&a.created));
+
+    let mut seen = HashSet::new();
+    let mut dropped = 0;
+
+    for order in newest_first {
+        let gamekey = &order.gamekey;
+
+        order
+            .subproducts
+            .retain(|subproduct| match &subproduct.machine_name {
+                Some(machine_name) if !seen.insert(machine_name.clone()) => {
+                    debug!(
+                        "{} in {} is in a newer order, skipping",
+                        machine_name, gamekey
+                    );
+                    dropped += 1;
+                    false
+                }
+                _ => true,
+            });
+    }
+
+    dropped
+}
+
+/// What became of a file `fetch_file` was asked for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fetched {
+    /// Downloaded, and passed its checks if they're on.
+    Downloaded,
+    /// Not downloaded, e.g. a valid copy was already there or there wasn't
+    /// room for it.
+    Skipped,
+    /// Downloaded, but failed its checks, so it was removed or quarantined.
+    Invalid,
+}
+
+/// Where `fetch_file` puts a file.
+#[derive(Clone, Copy)]
+enum Destination<'a> {
+    /// Wherever the folder layout puts it under this folder, unless a valid
+    /// copy is already there.
+    Folder(&'a Path),
+    /// Exactly this path, replacing what's there.
+    Replace(&'a Path),
+}
+
+/// Hashes a download as it's written, using whichever checksum humble gave us.
+enum StreamHasher {
+    Sha1(Sha1),
+    Md5(Md5),
+    Both(Sha1, Md5),
+    Unchecked,
+}
+
+impl StreamHasher {
+    fn new(download_struct: &DownloadStruct, all_hashes: bool) -> Self {
+        if all_hashes && download_struct.sha1.is_some() && download_struct.md5.is_some() {
+            StreamHasher::Both(Sha1::new(), Md5::new())
+        } else if download_struct.sha1.is_some() {
+            StreamHasher::Sha1(Sha1::new())
+        } else if download_struct.md5.is_some() {
+            StreamHasher::Md5(Md5::new())
+        } else {
+            StreamHasher::Unchecked
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            StreamHasher::Sha1(hasher) => hasher.update(data),
+            StreamHasher::Md5(hasher) => hasher.update(data),
+            StreamHasher::Both(sha1, md5) => {
+                sha1.update(data);
+                md5.update(data);
+            }
+            StreamHasher::Unchecked => {}
+        }
+    }
+
+    /// Hashes everything `reader` has, e.g. the part of a file an earlier run
+    /// already downloaded.
+    async fn update_from<R: Read + Unpin>(
+        &mut self,
+        mut reader: R,
+        cancelled: &AtomicBool,
+    ) -> Result<(), ApiError> {
+        if let StreamHasher::Unchecked = self {
+            return Ok(());
+        }
+
+        let mut buffer = vec![0; HASH_BUFFER_SIZE];
+
+        loop {
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(ApiError::Cancelled);
+            }
+
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(());
+            }
+
+            self.update(&buffer[..read]);
+        }
+    }
+
+    fn matches(self, download_struct: &DownloadStruct) -> bool {
+        match (self, &download_struct.sha1, &download_struct.md5) {
+            (StreamHasher::Sha1(hasher), Some(expected_hash), _) => {
+                hash_matches("sha1", expected_hash, &format!("{:x}", hasher.finalize()))
+            }
+            (StreamHasher::Md5(hasher), _, Some(expected_hash)) => {
+                hash_matches("md5", expected_hash, &format!("{:x}", hasher.finalize()))
+            }
+            (StreamHasher::Both(sha1, md5), Some(expected_sha1), Some(expected_md5)) => {
+                // both are checked so both mismatches get reported
+                let sha1_matches =
+                    hash_matches("sha1", expected_sha1, &format!("{:x}", sha1.finalize()));
+                let md5_matches =
+                    hash_matches("md5", expected_md5, &format!("{:x}", md5.finalize()));
+
+                sha1_matches && md5_matches
+            }
+            // yolo
+            _ => true,
+        }
+    }
+}
+
+/// Checks the file against its sha1, or its md5 if it has no sha1. With
+/// `all_hashes`, against every hash it has.
+async fn check_file_validity(
+    download_struct: &DownloadStruct,
+    path: &Path,
+    all_hashes: bool,
+    cancelled: &AtomicBool,
+) -> Result<bool, ApiError> {
+    if let (true, Some(expected_sha1), Some(expected_md5)) =
+        (all_hashes, &download_struct.sha1, &download_struct.md5)
+    {
+        let sha1 = sha1_digest(File::open(path).await?, cancelled).await?;
+        let md5 = md5_digest(File::open(path).await?, cancelled).await?;
+
+        let sha1_matches = hash_matches("sha1", expected_sha1, &sha1);
+        let md5_matches = hash_matches("md5", expected_md5, &md5);
+
+        Ok(sha1_matches && md5_matches)
+    } else if let Some(expected_hash) = &download_struct.sha1 {
+        let file_hash = sha1_digest(File::open(path).await?, cancelled).await?;
+        Ok(hash_matches("sha1", expected_hash, &file_hash))
+    } else if let Some(expected_hash) = &download_struct.md5 {
+        let file_hash = md5_digest(File::open(path).await?, cancelled).await?;
+        Ok(hash_matches("md5", expected_hash, &file_hash))
+    } else {
+        Ok(true)
+    }
+}
+
+/// Compares hex hashes ignoring case and surrounding whitespace, which humble
+/// hasn't been seen to send but costs nothing to allow.
+fn hash_matches(kind: &str, expected_hash: &str, file_hash: &str) -> bool {
+    let expected = expected_hash.trim().to_lowercase();
+
+    if expected != file_hash.trim().to_lowercase() {
+        println!("expected {} {} got {}", kind, expected_hash, file_hash);
+        return false;
+    }
+
+    if expected != expected_hash {
+        println!(
+            "warning: expected {} {:?} isn't plain lowercase hex, compared it normalized",
+            kind, expected_hash
+        );
+    }
+
+    true
+}
+
+async fn sha1_digest<R: Read + Unpin>(
+    reader: R,
+    cancelled: &AtomicBool,
+) -> Result<String, ApiError> {
+    digest::<Sha1, _>(reader, cancelled).await
+}
+
+async fn md5_digest<R: Read + Unpin>(
+    reader: R,
+    cancelled: &AtomicBool,
+) -> Result<String, ApiError> {
+    digest::<Md5, _>(reader, cancelled).await
+}
+
+async fn digest<D: Digest, R: Read + Unpin>(
+    mut reader: R,
+    cancelled: &AtomicBool,
+) -> Result<String, ApiError>
+where
+    sha1::digest::Output<D>: std::fmt::LowerHex,
+{
+    let mut hasher = D::new();
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            return Err(ApiError::Cancelled);
+        }
+
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+
+    #[test]
+    fn hashes_match_whatever_their_case_and_padding() {
+        assert!(hash_matches("md5", EMPTY_MD5, EMPTY_MD5));
+        assert!(hash_matches(
+            "md5",
+            "D41D8CD98F00B204E9800998ECF8427E",
+            EMPTY_MD5
+        ));
+        assert!(hash_matches(
+            "md5",
+            " d41d8cd98f00b204e9800998ECF8427E\n",
+            EMPTY_MD5
+        ));
+    }
+
+    #[test]
+    fn sanitized_names_stay_in_their_folder() {
+        assert_eq!(sanitize_file_name("windows"), "windows");
+        assert_eq!(sanitize_file_name("../../etc"), ".._.._etc");
+        assert_eq!(sanitize_file_name(".."), "_");
+        assert_eq!(sanitize_file_name(" . "), "_");
+        assert_eq!(sanitize_file_name("C:\\Windows"), "C__Windows");
+    }
+
+    #[test]
+    fn reads_where_a_content_range_starts() {
+        assert_eq!(content_range_start("bytes 20-44/45"), Some(20));
+        assert_eq!(content_range_start("bytes 0-0/1"), Some(0));
+        assert_eq!(content_range_start("bytes */45"), None);
+        assert_eq!(content_range_start("items 20-44/45"), None);
+    }
+
+    #[test]
+    fn different_hashes_dont_match() {
+        assert!(!hash_matches(
+            "md5",
+            "D41D8CD98F00B204E9800998ECF8427F",
+            EMPTY_MD5
+        ));
+        assert!(!hash_matches("md5", "", EMPTY_MD5));
+    }
 }
@@ -0,0 +1,74 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_std::{fs, task};
+use serde::{Deserialize, Serialize};
+
+use crate::{state, types::DownloadStruct};
+
+/// What a `.part` file was started against, and how much of it is known to
+/// be on disk, kept next to it in `<name>.part.json`. A later run only
+/// carries on with the `.part` while the file humble lists is still the same
+/// one, so a file that changed upstream never gets its new bytes appended to
+/// the old ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PartState {
+    sha1: Option<String>,
+    md5: Option<String>,
+    size: Option<u64>,
+    /// Bytes from the start that were synced to disk, so a run that died
+    /// after writing more can carry on from here, whatever the `.part`'s
+    /// length says. 0 until the first save during the download.
+    #[serde(default)]
+    pub(crate) written: u64,
+}
+
+impl PartState {
+    pub(crate) fn new(file: &DownloadStruct, size: Option<u64>) -> Self {
+        let normalized =
+            |hash: &Option<String>| hash.as_ref().map(|hash| hash.trim().to_lowercase());
+
+        Self {
+            sha1: normalized(&file.sha1),
+            md5: normalized(&file.md5),
+            size,
+            written: 0,
+        }
+    }
+
+    /// Whether both were started against the same version of the file.
+    pub(crate) fn same_file(&self, other: &PartState) -> bool {
+        self.sha1 == other.sha1 && self.md5 == other.md5 && self.size == other.size
+    }
+
+    /// The state saved next to `part`, if there's a readable one.
+    pub(crate) async fn load(part: &Path) -> Option<Self> {
+        let contents = fs::read(sidecar_path(part)).await.ok()?;
+
+        serde_json::from_slice(&contents).ok()
+    }
+
+    pub(crate) async fn save(&self, part: &Path) -> io::Result<()> {
+        let sidecar = sidecar_path(part);
+        let contents = serde_json::to_vec(self)?;
+
+        task::spawn_blocking(move || state::write_atomic(&sidecar, &contents)).await
+    }
+
+    /// Removes the state next to `part`, once the `.part` is gone or about to
+    /// be started over.
+    pub(crate) async fn remove(part: &Path) -> io::Result<()> {
+        match fs::remove_file(sidecar_path(part)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn sidecar_path(part: &Path) -> PathBuf {
+    let mut sidecar = part.as_os_str().to_owned();
+    sidecar.push(".json");
+    PathBuf::from(sidecar)
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+/// Byte counts summed over every file a client has started downloading, safe
+/// to read from another task while downloads are running.
+#[derive(Debug, Default)]
+pub struct Progress {
+    downloaded: AtomicU64,
+    total: AtomicU64,
+    platforms: Mutex<BTreeMap<String, PlatformTotals>>,
+}
+
+/// Files downloaded for one platform, and their combined size.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlatformTotals {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Progress {
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Expected size of all started files. Files of unknown size grow this as
+    /// they download, so it never falls behind `downloaded`.
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Files that finished downloading and passed verification, by platform.
+    pub fn by_platform(&self) -> Vec<(String, PlatformTotals)> {
+        let platforms = self.platforms.lock().unwrap();
+
+        platforms
+            .iter()
+            .map(|(platform, totals)| (platform.clone(), *totals))
+            .collect()
+    }
+
+    pub(crate) fn start_file(&self, expected_size: Option<u64>) {
+        if let Some(expected_size) = expected_size {
+            self.total.fetch_add(expected_size, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes the part of a file that was already downloaded off the total,
+    /// so the totals only count what this run downloads.
+    pub(crate) fn resume_file(&self, existing: u64) {
+        self.total.fetch_sub(existing, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_chunk(&self, len: u64, size_known: bool) {
+        self.downloaded.fetch_add(len, Ordering::Relaxed);
+
+        if !size_known {
+            self.total.fetch_add(len, Ordering::Relaxed);
+        }
+    }
+
+    fn finish_file(&self, platform: &str, bytes: u64) {
+        let mut platforms = self.platforms.lock().unwrap();
+        let totals = platforms.entry(platform.to_owned()).or_default();
+
+        totals.files += 1;
+        totals.bytes += bytes;
+    }
+}
+
+/// What's happening to a single file, sent to `HBClient::progress_events` so
+/// a front end can show more than the overall byte counts. `gamekey` is
+/// `None` for downloads that aren't part of an order. `Progress` counts the
+/// bytes since the last `Progress` event for the file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressEvent {
+    Started {
+        gamekey: Option<String>,
+        file: String,
+        size: Option<u64>,
+    },
+    Progress {
+        gamekey: Option<String>,
+        file: String,
+        bytes: u64,
+    },
+    Done {
+        gamekey: Option<String>,
+        file: String,
+        valid: bool,
+    },
+    Error {
+        gamekey: Option<String>,
+        file: String,
+        message: String,
+    },
+}
+
+/// Feeds one file's progress into the client's totals and its event channel.
+/// Tokio's senders need `&mut self`, so each event goes out through a clone
+/// of the client's, which shares the channel's capacity rather than adding to
+/// it.
+pub(crate) struct FileProgress<'a> {
+    progress: &'a Progress,
+    events: Option<&'a Sender<ProgressEvent>>,
+    gamekey: Option<&'a str>,
+    file: &'a str,
+    platform: &'a str,
+    size_known: bool,
+    // ranged downloads report chunks from several connections at once
+    written: AtomicU64,
+    // bytes from progress events that didn't fit in the channel
+    unsent: AtomicU64,
+}
+
+impl<'a> FileProgress<'a> {
+    pub(crate) async fn start(
+        progress: &'a Progress,
+        events: Option<&'a Sender<ProgressEvent>>,
+        gamekey: Option<&'a str>,
+        file: &'a str,
+        platform: &'a str,
+        size: Option<u64>,
+    ) -> Self {
+        progress.start_file(size);
+
+        let file_progress = Self {
+            progress,
+            events,
+            gamekey,
+            file,
+            platform,
+            size_known: size.is_some(),
+            written: AtomicU64::new(0),
+            unsent: AtomicU64::new(0),
+        };
+
+        file_progress
+            .send(|gamekey, file| ProgressEvent::Started {
+                gamekey,
+                file,
+                size,
+            })
+            .await;
+
+        file_progress
+    }
+
+    /// Never waits for the receiver. While the channel is full, progress
+    /// events are dropped and their bytes added to the next one that fits.
+    pub(crate) fn chunk(&self, bytes: u64) {
+        self.progress.add_chunk(bytes, self.size_known);
+        self.written.fetch_add(bytes, Ordering::Relaxed);
+        self.report(bytes);
+    }
+
+    /// The file is being carried on from `existing` bytes an earlier run left.
+    /// They count towards the file, and its progress events, but not towards
+    /// what this run downloaded.
+    pub(crate) fn resumed(&self, existing: u64) {
+        if self.size_known {
+            self.progress.resume_file(existing);
+        }
+        self.written.fetch_add(existing, Ordering::Relaxed);
+        self.report(existing);
+    }
+
+    fn report(&self, bytes: u64) {
+        let events = match self.events {
+            Some(events) => events,
+            None => return,
+        };
+
+        let bytes = bytes + self.unsent.swap(0, Ordering::Relaxed);
+        let event = ProgressEvent::Progress {
+            gamekey: self.gamekey.map(str::to_owned),
+            file: self.file.to_owned(),
+            bytes,
+        };
+
+        if let Err(TrySendError::Full(_)) = events.clone().try_send(event) {
+            self.unsent.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) async fn done(&self, valid: bool) {
+        if valid {
+            self.progress
+                .finish_file(self.platform, self.written.load(Ordering::Relaxed));
+        }
+
+        // so the progress events always add up to the whole file
+        let unsent = self.unsent.swap(0, Ordering::Relaxed);
+        if unsent > 0 {
+            self.send(|gamekey, file| ProgressEvent::Progress {
+                gamekey,
+                file,
+                bytes: unsent,
+            })
+            .await;
+        }
+
+        self.send(|gamekey, file| ProgressEvent::Done {
+            gamekey,
+            file,
+            valid,
+        })
+        .await;
+    }
+
+    /// Waits for room in the channel, so only ever holds up the start or end
+    /// of a file, never its data.
+    async fn send(&self, event: impl FnOnce(Option<String>, String) -> ProgressEvent) {
+        if let Some(events) = self.events {
+            // nobody listening any more isn't a reason to stop downloading
+            let _ = events
+                .clone()
+                .send(event(self.gamekey.map(str::to_owned), self.file.to_owned()))
+                .await;
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::StatusCode;
+
+// backoff stops growing here, however many attempts have failed
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often and how patiently failed requests get retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, 0 never retries.
+    pub retries: u32,
+    /// The delay before the first retry, doubling for each one after.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The backoff for the given retry, picked at random from its upper half
+    /// so clients that failed together don't all retry in lockstep.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY);
+
+        let millis = backoff.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2, millis + 1))
+    }
+}
+
+/// Failures that might go away by themselves: dropped connections, timeouts,
+/// rate limiting and server errors.
+pub(crate) fn should_retry(result: &Result<reqwest::Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+        }
+        Err(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+    }
+}
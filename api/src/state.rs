@@ -0,0 +1,88 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+/// Replaces `path` with `contents` so that a reader, or the next run after a
+/// crash, sees either the old file or the new one and never half of either.
+/// The new contents go to a temporary file next to it first, which is then
+/// renamed over the old one.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    let mut file = File::create(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        env,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    /// A folder of its own for a test, removed when dropped.
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let path = env::temp_dir().join(format!(
+                "hb-api-unit-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(&path).unwrap();
+
+            Self(path)
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn a_write_that_fails_leaves_the_old_file() {
+        let folder = TempDir::new();
+        let path = folder.path().join("state.json");
+        write_atomic(&path, b"old").unwrap();
+
+        // the temporary file can't be created, like a write cut short before
+        // the rename
+        fs::create_dir(folder.path().join("state.json.tmp")).unwrap();
+
+        assert!(write_atomic(&path, b"new").is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+    }
+
+    #[test]
+    fn a_temporary_file_left_by_a_crash_is_replaced() {
+        let folder = TempDir::new();
+        let path = folder.path().join("state.json");
+        write_atomic(&path, b"old").unwrap();
+
+        fs::write(folder.path().join("state.json.tmp"), b"half writ").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!folder.path().join("state.json.tmp").exists());
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    convert::TryFrom,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    sanitize_file_name,
+    types::{Download, Order, Subproduct},
+};
+
+/// A destination path pattern such as `{bundle}/{platform}/{name}.{ext}`,
+/// relative to the download folder.
+///
+/// Placeholders:
+/// - `{bundle}`: the order's product name
+/// - `{gamekey}`: the order's gamekey
+/// - `{subproduct}`: the subproduct's name
+/// - `{machine_name}`: the subproduct's machine name
+/// - `{platform}`: the download's platform
+/// - `{filename}`: the file name from the download url
+/// - `{name}`: that file name without its extension
+/// - `{ext}`: that file name's extension
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct FilenameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Placeholder {
+    Bundle,
+    Gamekey,
+    Subproduct,
+    MachineName,
+    Platform,
+    Filename,
+    Name,
+    Ext,
+}
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("filename template is empty")]
+    Empty,
+    #[error("unknown placeholder {{{0}}} in filename template")]
+    UnknownPlaceholder(String),
+    #[error("unmatched brace in filename template")]
+    UnmatchedBrace,
+    #[error("filename template must be a relative path inside the download folder")]
+    NotRelative,
+}
+
+/// The order and subproduct a download belongs to, when there is one.
+#[derive(Clone, Copy)]
+pub(crate) struct Origin<'a> {
+    pub order: &'a Order,
+    pub subproduct: &'a Subproduct,
+}
+
+impl FilenameTemplate {
+    /// Fills in the template, or returns `None` if any placeholder has no
+    /// value for this download, or the path would lead out of the download
+    /// folder, so the caller can fall back to the plain name.
+    pub(crate) fn render(
+        &self,
+        origin: Option<Origin>,
+        download: &Download,
+        file_name: &str,
+    ) -> Option<PathBuf> {
+        let file_path = Path::new(file_name);
+        let mut rendered = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Placeholder(placeholder) => {
+                    let value = match placeholder {
+                        Placeholder::Bundle => origin.map(|o| o.order.product.human_name.as_str()),
+                        Placeholder::Gamekey => origin.map(|o| o.order.gamekey.as_str()),
+                        Placeholder::Subproduct => origin.map(|o| o.subproduct.human_name.as_str()),
+                        Placeholder::MachineName => {
+                            origin.and_then(|o| o.subproduct.machine_name.as_deref())
+                        }
+                        Placeholder::Platform => Some(download.platform.as_str()),
+                        Placeholder::Filename => Some(file_name),
+                        Placeholder::Name => file_path.file_stem().and_then(|s| s.to_str()),
+                        Placeholder::Ext => file_path.extension().and_then(|s| s.to_str()),
+                    };
+
+                    match value.map(sanitize_file_name) {
+                        Some(value) if !value.is_empty() => rendered.push_str(&value),
+                        _ => return None,
+                    }
+                }
+            }
+        }
+
+        let rendered = PathBuf::from(rendered);
+
+        // values can't add separators, but the path is checked again as a
+        // whole in case one makes a `..` or a drive prefix with a literal
+        if stays_inside(&rendered) {
+            Some(rendered)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` is relative and has no `..`, so it can't lead anywhere but
+/// somewhere inside the folder it's joined to.
+fn stays_inside(path: &Path) -> bool {
+    path.components()
+        .any(|component| matches!(component, Component::Normal(_)))
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        if template.trim().is_empty() {
+            return Err(TemplateError::Empty);
+        }
+
+        if !stays_inside(Path::new(template)) || template.split(['/', '\\']).any(|s| s == "..") {
+            return Err(TemplateError::NotRelative);
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(start) if rest[start..].starts_with('{') => {
+                    if start > 0 {
+                        parts.push(Part::Literal(rest[..start].to_owned()));
+                    }
+
+                    let end = rest[start..]
+                        .find('}')
+                        .ok_or(TemplateError::UnmatchedBrace)?
+                        + start;
+
+                    parts.push(Part::Placeholder(parse_placeholder(&rest[start + 1..end])?));
+                    rest = &rest[end + 1..];
+                }
+                Some(_) => return Err(TemplateError::UnmatchedBrace),
+                None => {
+                    parts.push(Part::Literal(rest.to_owned()));
+                    rest = "";
+                }
+            }
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl TryFrom<String> for FilenameTemplate {
+    type Error = TemplateError;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        template.parse()
+    }
+}
+
+fn parse_placeholder(name: &str) -> Result<Placeholder, TemplateError> {
+    match name {
+        "bundle" => Ok(Placeholder::Bundle),
+        "gamekey" => Ok(Placeholder::Gamekey),
+        "subproduct" => Ok(Placeholder::Subproduct),
+        "machine_name" => Ok(Placeholder::MachineName),
+        "platform" => Ok(Placeholder::Platform),
+        "filename" => Ok(Placeholder::Filename),
+        "name" => Ok(Placeholder::Name),
+        "ext" => Ok(Placeholder::Ext),
+        _ => Err(TemplateError::UnknownPlaceholder(name.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_takes_paths_inside_the_download_folder() {
+        for template in &[
+            "/srv/{filename}",
+            "../{filename}",
+            "{bundle}/../../{filename}",
+            ".",
+        ] {
+            assert!(
+                matches!(
+                    template.parse::<FilenameTemplate>(),
+                    Err(TemplateError::NotRelative)
+                ),
+                "{}",
+                template
+            );
+        }
+
+        assert!("./{platform}/{filename}"
+            .parse::<FilenameTemplate>()
+            .is_ok());
+    }
+
+    #[test]
+    fn renders_values_that_look_like_paths_inside_the_download_folder() {
+        let template: FilenameTemplate = "{platform}/{filename}".parse().unwrap();
+        let download = Download {
+            platform: "..".to_owned(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            template.render(None, &download, "book.pdf"),
+            Some(PathBuf::from("_/book.pdf"))
+        );
+        assert_eq!(
+            template.render(None, &download, "/etc/passwd"),
+            Some(PathBuf::from("_/_etc_passwd"))
+        );
+    }
+}
@@ -9,15 +9,70 @@ pub struct OrderListItem {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Order {
+    pub gamekey: String,
+    /// When the order was placed, e.g. `2019-03-05T17:04:53.339320`.
+    pub created: Option<String>,
+    pub product: Product,
     pub subproducts: Vec<Subproduct>,
 }
 
+impl Order {
+    /// Combined size of every file for these platforms, or `None` if humble
+    /// didn't list the size of one of them.
+    pub fn total_size(&self, platforms: &[String]) -> Option<u64> {
+        self.subproducts
+            .iter()
+            .flat_map(|subproduct| &subproduct.downloads)
+            .filter(|download| platforms.contains(&download.platform))
+            .flat_map(|download| &download.download_struct)
+            .map(|file| file.file_size)
+            .sum()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Product {
+    #[serde(rename = "human_name")]
+    pub human_name: String,
+    /// What kind of purchase the order was, e.g. `bundle` or `storefront`.
+    pub category: Option<String>,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Subproduct {
+    #[serde(rename = "human_name")]
+    pub human_name: String,
+    /// Stable slug for the title, the same across every order it appears in.
+    #[serde(rename = "machine_name")]
+    pub machine_name: Option<String>,
+    /// What kind of title it is, e.g. `game` or `ebook`, when humble says.
+    pub category: Option<String>,
     pub downloads: Vec<Download>,
 }
 
+impl Subproduct {
+    /// Whether `name` is this subproduct's machine name, or its display name
+    /// ignoring case.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.machine_name.as_deref() == Some(name) || self.human_name.eq_ignore_ascii_case(name)
+    }
+
+    /// Whether the subproduct, or failing that the order it's in, is in one
+    /// of `categories`, ignoring case. Ones without a category never are.
+    pub fn in_category(&self, product: &Product, categories: &[String]) -> bool {
+        let category = match self.category.as_deref().or(product.category.as_deref()) {
+            Some(category) => category,
+            None => return false,
+        };
+
+        categories
+            .iter()
+            .any(|wanted| wanted.eq_ignore_ascii_case(category))
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Download {
@@ -31,9 +86,50 @@ pub struct Download {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DownloadStruct {
+    /// Humble's label for the file, usually its format, e.g. `FLAC` or `EPUB`.
+    pub name: Option<String>,
     pub sha1: Option<String>,
     pub url: Option<Url>,
     pub md5: Option<String>,
+    #[serde(rename = "file_size")]
+    pub file_size: Option<u64>,
+    /// When humble last updated the file, in seconds since the unix epoch.
+    pub timestamp: Option<u64>,
+}
+
+impl DownloadStruct {
+    /// Whether the file is in `format`, going by its label or its extension.
+    pub fn is_format(&self, format: &str) -> bool {
+        let format = format.to_lowercase();
+
+        self.name.as_deref().map(str::to_lowercase).as_deref() == Some(&*format)
+            || self
+                .file_name()
+                .map(|name| name.to_lowercase().ends_with(&format!(".{}", format)))
+                .unwrap_or(false)
+    }
+
+    /// The label without its quality, so variants of one file compare equal,
+    /// e.g. `MP3 320kbps` and `MP3 V0` are both `mp3`. Words with a digit in
+    /// them and words like `hd` are taken to be the quality.
+    pub fn base_name(&self) -> Option<String> {
+        let name = self.name.as_deref()?.to_lowercase();
+
+        let words: Vec<&str> = name
+            .split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '-')
+            .filter(|word| !word.is_empty())
+            .filter(|word| !word.chars().any(|c| c.is_ascii_digit()))
+            .filter(|word| !matches!(*word, "hd" | "sd" | "hq" | "lq" | "high" | "low"))
+            .collect();
+
+        Some(words.join(" "))
+    }
+
+    /// The file name at the end of the download url, if it has one.
+    pub fn file_name(&self) -> Option<String> {
+        let url = url::Url::parse(&self.url.as_ref()?.web).ok()?;
+        crate::url_file_name(&url).map(str::to_owned)
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use hb_api::{ApiError, HBClient, HttpOptions};
+
+fn new_client(name: &str, value: &str) -> Result<HBClient, ApiError> {
+    let mut headers = HashMap::new();
+    headers.insert(name.to_owned(), value.to_owned());
+
+    HBClient::new(
+        std::env::temp_dir(),
+        headers,
+        vec!["ebook".to_owned()],
+        &HttpOptions::default(),
+    )
+}
+
+#[test]
+fn accepts_valid_headers() {
+    assert!(new_client("Cookie", "_simpleauth_sess=abc").is_ok());
+}
+
+#[test]
+fn rejects_header_values_with_line_breaks() {
+    // e.g. a cookie file with a second line
+    match new_client("Cookie", "_simpleauth_sess=abc\nsecond line") {
+        Err(ApiError::InvalidHeader(name)) => assert_eq!(name, "Cookie"),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("accepted a header with a line break"),
+    }
+}
+
+#[test]
+fn rejects_header_values_with_control_characters() {
+    assert!(matches!(
+        new_client("Cookie", "_simpleauth_sess=abc\u{7}"),
+        Err(ApiError::InvalidHeader(_))
+    ));
+}
+
+#[test]
+fn rejects_invalid_header_names() {
+    assert!(matches!(
+        new_client("Bad Header", "value"),
+        Err(ApiError::InvalidHeader(_))
+    ));
+}
+
+#[test]
+fn doesnt_put_header_values_in_the_error() {
+    let error = new_client("Cookie", "secret\nvalue").err().unwrap();
+
+    assert!(!error.to_string().contains("secret"));
+}
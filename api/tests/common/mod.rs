@@ -0,0 +1,117 @@
+//! Shared setup for the integration tests: a mock humble serving the orders
+//! in `fixtures/` and the files in `fixtures/files/`, and throwaway download
+//! folders. Fixture urls say `{server}` where the mock server's address goes.
+
+// each test binary uses a different part of this
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use hb_api::{types::Order, HBClient};
+
+pub use hb_test_support::{MockServer, Route};
+
+pub const GAMEKEY: &str = "tEsTgAmEkEy00001";
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+}
+
+/// A fixture's text with the server's address filled in.
+pub fn fixture(name: &str, server: &MockServer) -> String {
+    fs::read_to_string(fixtures().join(name))
+        .unwrap()
+        .replace("{server}", &server.url())
+}
+
+pub fn order(name: &str, server: &MockServer) -> Order {
+    serde_json::from_str(&fixture(name, server)).unwrap()
+}
+
+/// The contents of a file in `fixtures/files/`.
+pub fn file(name: &str) -> Vec<u8> {
+    fs::read(fixtures().join("files").join(name)).unwrap()
+}
+
+/// A mock server with the order list, `order.json` under `GAMEKEY` and every
+/// file in `fixtures/files/` under `/files/`.
+pub fn humble() -> MockServer {
+    let server = MockServer::start();
+
+    server.route(
+        "/api/v1/user/order",
+        Route::ok(fixture("order_list.json", &server)),
+    );
+    serve_order(&server, GAMEKEY, "order.json");
+
+    for entry in fs::read_dir(fixtures().join("files")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        server.route(
+            &format!("/files/{}", name),
+            Route::ok(fs::read(&path).unwrap()),
+        );
+    }
+
+    server
+}
+
+pub fn serve_order(server: &MockServer, gamekey: &str, fixture_name: &str) {
+    server.route(
+        &format!("/api/v1/order/{}", gamekey),
+        Route::ok(fixture(fixture_name, server)),
+    );
+}
+
+/// A client talking to `server`, downloading `platforms` into `folder`, that
+/// doesn't wait around between retries.
+pub fn client(server: &MockServer, folder: &Path, platforms: &[&str]) -> HBClient {
+    HBClient::with_client(
+        reqwest::Client::new(),
+        folder.to_path_buf(),
+        platforms
+            .iter()
+            .map(|platform| platform.to_string())
+            .collect(),
+    )
+    .base_url(&format!("{}/api/v1", server.url()))
+    .retry(hb_api::RetryPolicy {
+        retries: 0,
+        ..Default::default()
+    })
+}
+
+/// A folder under the system temp folder that's removed again on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "hb-api-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{MockServer, Route, TempDir};
+use futures::future;
+use hb_api::types::{Download, DownloadStruct, Url};
+
+const FILES: usize = 6;
+// big enough that every worker is still downloading when the others start
+const FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// `FILES` downloads of generated files, which the server takes byte range
+/// requests for.
+fn downloads(server: &MockServer) -> Vec<Download> {
+    (0..FILES)
+        .map(|i| {
+            let path = format!("/files/{}.bin", i);
+            server.route(&path, Route::generated(FILE_SIZE).accept_ranges());
+
+            Download {
+                platform: "linux".to_owned(),
+                download_struct: vec![DownloadStruct {
+                    file_size: Some(FILE_SIZE),
+                    url: Some(Url {
+                        web: format!("{}{}", server.url(), path),
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn caps_connections_per_host_across_files() {
+    let server = MockServer::start();
+    let downloads = downloads(&server);
+    let folder = TempDir::new();
+    let client =
+        common::client(&server, folder.path(), &["linux"]).max_connections_per_host(Some(2));
+
+    future::try_join_all(downloads.iter().map(|download| client.download(download)))
+        .await
+        .unwrap();
+
+    assert!(
+        server.most_in_flight() <= 2,
+        "{} in flight",
+        server.most_in_flight()
+    );
+    for i in 0..FILES {
+        assert_eq!(
+            folder
+                .path()
+                .join(format!("{}.bin", i))
+                .metadata()
+                .unwrap()
+                .len(),
+            FILE_SIZE
+        );
+    }
+}
+
+#[tokio::test]
+async fn caps_connections_per_host_across_byte_ranges() {
+    let server = MockServer::start();
+    let downloads = downloads(&server);
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["linux"])
+        .download_connections(4)
+        .max_connections_per_host(Some(3));
+
+    future::try_join_all(downloads.iter().map(|download| client.download(download)))
+        .await
+        .unwrap();
+
+    // the first request of each file is dropped for its ranges, and the
+    // server may still be sending into it as they start, so only ranges count
+    assert!(
+        server.most_ranges_in_flight() <= 3,
+        "{} in flight",
+        server.most_ranges_in_flight()
+    );
+    assert!(server
+        .requests("/files/0.bin")
+        .iter()
+        .any(|request| request.range.is_some()));
+}
+
+#[tokio::test]
+async fn caps_connections_to_the_host_links_redirect_to() {
+    let cdn = MockServer::start();
+    let links = MockServer::start();
+    let mut downloads = downloads(&cdn);
+    let folder = TempDir::new();
+    let client = common::client(&cdn, folder.path(), &["linux"]).max_connections_per_host(Some(2));
+
+    // every other link goes through another host that redirects to the cdn,
+    // like humble's do, the rest straight to it
+    for (i, download) in downloads.iter_mut().enumerate().step_by(2) {
+        let path = format!("/links/{}.bin", i);
+        links.route(
+            &path,
+            Route::redirect(format!("{}/files/{}.bin", cdn.url(), i)),
+        );
+        download.download_struct[0].url = Some(Url {
+            web: format!("{}{}", links.url().replace("127.0.0.1", "localhost"), path),
+        });
+    }
+
+    // where the links go is only found out by following one
+    client.download(&downloads[0]).await.unwrap();
+    future::try_join_all(
+        downloads[1..]
+            .iter()
+            .map(|download| client.download(download)),
+    )
+    .await
+    .unwrap();
+
+    assert!(
+        cdn.most_in_flight() <= 2,
+        "{} in flight",
+        cdn.most_in_flight()
+    );
+    assert_eq!(links.hits("/links/2.bin"), 1);
+}
+
+#[tokio::test]
+async fn doesnt_cap_connections_by_default() {
+    let server = MockServer::start();
+    let downloads = downloads(&server);
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["linux"]);
+
+    future::try_join_all(downloads.iter().map(|download| client.download(download)))
+        .await
+        .unwrap();
+
+    assert!(
+        server.most_in_flight() > 2,
+        "{} in flight",
+        server.most_in_flight()
+    );
+}
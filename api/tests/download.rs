@@ -0,0 +1,672 @@
+mod common;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::{collections::HashMap, fs, time::Duration};
+
+use common::{MockServer, Route, TempDir, GAMEKEY};
+use hb_api::{
+    types::{Download, DownloadStruct, Order, Url},
+    ApiError, HBClient,
+};
+use hb_test_support::generated_byte;
+use sha1::{Digest, Sha1};
+
+#[tokio::test]
+async fn downloads_and_verifies_wanted_platforms() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook", "linux"]);
+
+    let orders = client.list_orders().await.unwrap();
+    assert_eq!(orders.len(), 1);
+
+    let order = client.get_order(&orders[0].gamekey).await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    for name in &["book.epub", "book.pdf", "game.sh"] {
+        assert_eq!(
+            fs::read(folder.path().join(name)).unwrap(),
+            common::file(name),
+            "{}",
+            name
+        );
+        assert!(!folder.path().join(format!("{}.part", name)).exists());
+    }
+
+    assert!(!folder.path().join("game-setup.exe").exists());
+    assert_eq!(server.hits("/files/game-setup.exe"), 0);
+    assert_eq!(client.progress().downloaded(), 45 + 24 + 24);
+}
+
+#[tokio::test]
+async fn skips_valid_files_already_downloaded() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    fs::write(folder.path().join("book.epub"), common::file("book.epub")).unwrap();
+
+    let order = client.get_order(GAMEKEY).await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(server.hits("/files/book.epub"), 0);
+    assert_eq!(server.hits("/files/book.pdf"), 1);
+
+    // and nothing at all the second time round
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(server.hits("/files/book.epub"), 0);
+    assert_eq!(server.hits("/files/book.pdf"), 1);
+}
+
+#[tokio::test]
+async fn links_files_unchanged_since_the_previous_session() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let order = common::order("order.json", &server);
+
+    common::client(&server, folder.path(), &["ebook"])
+        .session_folder("2024-06-01", None)
+        .verify_checkpoint(Some(Duration::from_secs(60)))
+        .download_order(&order)
+        .await
+        .unwrap();
+
+    common::client(&server, folder.path(), &["ebook"])
+        .session_folder("2024-06-02", Some("2024-06-01"))
+        .verify_checkpoint(Some(Duration::from_secs(60)))
+        .download_order(&order)
+        .await
+        .unwrap();
+
+    assert_eq!(server.hits("/files/book.epub"), 1);
+    for name in &["book.epub", "book.pdf"] {
+        let linked = folder.path().join("2024-06-02").join(name);
+        assert_eq!(fs::read(&linked).unwrap(), common::file(name), "{}", name);
+        #[cfg(unix)]
+        assert_eq!(
+            linked.metadata().unwrap().ino(),
+            folder
+                .path()
+                .join("2024-06-01")
+                .join(name)
+                .metadata()
+                .unwrap()
+                .ino()
+        );
+    }
+
+    // the state is shared by every session, not kept in any one of them
+    assert!(folder.path().join(".hb-downloader-verified.json").exists());
+    assert!(!folder
+        .path()
+        .join("2024-06-02")
+        .join(".hb-downloader-verified.json")
+        .exists());
+}
+
+#[tokio::test]
+async fn removes_downloads_that_fail_their_checksum() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    // the right size, so only the hash can catch it
+    let mut corrupt = common::file("book.pdf");
+    corrupt[0] ^= 0xff;
+    server.route("/files/book.pdf", Route::ok(corrupt));
+
+    let order = client.get_order(GAMEKEY).await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    assert!(!folder.path().join("book.pdf").exists());
+    assert!(!folder.path().join("book.pdf.part").exists());
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+}
+
+#[tokio::test]
+async fn quarantines_downloads_under_their_own_platform_folder() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let books = TempDir::new();
+    let mut platform_folders = HashMap::new();
+    platform_folders.insert("ebook".to_owned(), books.path().to_path_buf());
+    let client = common::client(&server, folder.path(), &["ebook"])
+        .platform_folders(platform_folders)
+        .quarantine_on_failure(true);
+
+    let mut corrupt = common::file("book.pdf");
+    corrupt[0] ^= 0xff;
+    server.route("/files/book.pdf", Route::ok(corrupt.clone()));
+
+    let order = client.get_order(GAMEKEY).await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    let quarantined = books.path().join("quarantine").join("book.pdf.bad");
+    assert_eq!(fs::read(&quarantined).unwrap(), corrupt);
+    assert!(books
+        .path()
+        .join("quarantine")
+        .join("book.pdf.bad.txt")
+        .exists());
+    assert!(!books.path().join("book.pdf").exists());
+    assert!(!folder.path().join("quarantine").exists());
+}
+
+#[tokio::test]
+async fn replaces_existing_files_that_fail_their_checksum() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    let mut corrupt = common::file("book.epub");
+    corrupt[0] ^= 0xff;
+    fs::write(folder.path().join("book.epub"), corrupt).unwrap();
+
+    let order = client.get_order(GAMEKEY).await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(server.hits("/files/book.epub"), 1);
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+}
+
+#[tokio::test]
+async fn skips_urls_without_a_file_name() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    let download = Download {
+        platform: "ebook".to_owned(),
+        download_struct: vec![DownloadStruct {
+            url: Some(Url {
+                web: format!("{}/", server.url()),
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    client.download(&download).await.unwrap();
+
+    assert_eq!(server.hits("/"), 0);
+    assert_eq!(fs::read_dir(folder.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn download_skips_unwanted_platforms() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    let order = common::order("order.json", &server);
+    let windows = &order.subproducts[1].downloads[1];
+    assert_eq!(windows.platform, "windows");
+
+    client.download(windows).await.unwrap();
+
+    assert_eq!(server.hits("/files/game-setup.exe"), 0);
+    assert!(!folder.path().join("game-setup.exe").exists());
+}
+
+#[tokio::test]
+async fn download_url_ignores_the_platform_filter() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    client
+        .download_url(&format!("{}/files/game-setup.exe?t=scrubbed", server.url()))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        fs::read(folder.path().join("game-setup.exe")).unwrap(),
+        common::file("game-setup.exe")
+    );
+}
+
+#[tokio::test]
+async fn skips_files_with_an_empty_url() {
+    let server = common::humble();
+    common::serve_order(&server, "eMpTyWeBgAmEkEy1", "order_empty_web.json");
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    let order = client.get_order("eMpTyWeBgAmEkEy1").await.unwrap();
+    assert_eq!(
+        order.subproducts[0].downloads[0].download_struct[0]
+            .url
+            .as_ref()
+            .unwrap()
+            .web,
+        ""
+    );
+
+    // the empty one doesn't stop the rest of the order
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+    assert_eq!(client.planned_files(&order).len(), 1);
+}
+
+#[tokio::test]
+async fn redownloads_files_without_a_hash_when_their_size_is_wrong() {
+    let server = common::humble();
+    common::serve_order(&server, "nOhAsHgAmEkEy001", "order_no_hash.json");
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    fs::write(folder.path().join("book.pdf"), "truncated").unwrap();
+
+    let order = client.get_order("nOhAsHgAmEkEy001").await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(server.hits("/files/book.pdf"), 1);
+    assert_eq!(
+        fs::read(folder.path().join("book.pdf")).unwrap(),
+        common::file("book.pdf")
+    );
+
+    // the right size is all that can be checked, so it's kept
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(server.hits("/files/book.pdf"), 1);
+}
+
+#[tokio::test]
+async fn keeps_files_without_a_hash_of_any_size_when_not_checking_sizes() {
+    let server = common::humble();
+    common::serve_order(&server, "nOhAsHgAmEkEy001", "order_no_hash.json");
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]).check_file_size(false);
+
+    fs::write(folder.path().join("book.pdf"), "truncated").unwrap();
+
+    let order = client.get_order("nOhAsHgAmEkEy001").await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(server.hits("/files/book.pdf"), 0);
+}
+
+/// Gets `book.epub` cut off 20 bytes in, leaving its `.part`, then serves
+/// the whole of it with range support.
+async fn interrupted_download(server: &MockServer, client: &HBClient, order: &Order) {
+    server.route(
+        "/files/book.epub",
+        Route::ok(common::file("book.epub")).hang_up_at(20),
+    );
+    assert!(client.download_order(order).await.is_err());
+
+    server.route(
+        "/files/book.epub",
+        Route::ok(common::file("book.epub")).accept_ranges(),
+    );
+}
+
+#[tokio::test]
+async fn carries_on_from_a_partial_download() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+    let order = client.get_order(GAMEKEY).await.unwrap();
+
+    interrupted_download(&server, &client, &order).await;
+    let part = folder.path().join("book.epub.part");
+    assert_eq!(fs::read(&part).unwrap(), &common::file("book.epub")[..20]);
+    let downloaded = client.progress().downloaded();
+    // book.pdf may or may not have finished before the error stopped the run
+    let pdf = if folder.path().join("book.pdf").exists() {
+        0
+    } else {
+        24
+    };
+
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+    assert!(!part.exists());
+    assert!(!folder.path().join("book.epub.part.json").exists());
+
+    let ranges: Vec<_> = server
+        .requests("/files/book.epub")
+        .into_iter()
+        .map(|request| request.range)
+        .collect();
+    assert_eq!(ranges, vec![None, None, Some("bytes=20-".to_owned())]);
+    assert_eq!(client.progress().downloaded() - downloaded, (45 - 20) + pdf);
+}
+
+#[tokio::test]
+async fn starts_partial_downloads_over_without_range_support() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+    let order = client.get_order(GAMEKEY).await.unwrap();
+
+    interrupted_download(&server, &client, &order).await;
+    server.route("/files/book.epub", Route::ok(common::file("book.epub")));
+
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+    assert_eq!(
+        server.requests("/files/book.epub").last().unwrap().range,
+        None
+    );
+}
+
+#[tokio::test]
+async fn starts_over_partial_downloads_it_knows_nothing_about() {
+    let server = common::humble();
+    server.route(
+        "/files/book.epub",
+        Route::ok(common::file("book.epub")).accept_ranges(),
+    );
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    // e.g. left by a version that didn't note what the .part was for
+    fs::write(folder.path().join("book.epub.part"), [0; 20]).unwrap();
+
+    let order = client.get_order(GAMEKEY).await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+    assert_eq!(server.hits("/files/book.epub"), 1);
+}
+
+#[tokio::test]
+async fn removes_partial_downloads_that_were_wrong() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+    let order = client.get_order(GAMEKEY).await.unwrap();
+
+    interrupted_download(&server, &client, &order).await;
+    fs::write(folder.path().join("book.epub.part"), [0; 20]).unwrap();
+
+    client.download_order(&order).await.unwrap();
+
+    assert!(!folder.path().join("book.epub").exists());
+    assert!(!folder.path().join("book.epub.part").exists());
+
+    // so the next run gets the whole file
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+    assert_eq!(
+        server.requests("/files/book.epub").last().unwrap().range,
+        None
+    );
+}
+
+/// A download of `notes.txt` with `body`'s size and sha1.
+fn notes(server: &MockServer, body: &[u8]) -> Download {
+    Download {
+        platform: "ebook".to_owned(),
+        download_struct: vec![DownloadStruct {
+            sha1: Some(format!("{:x}", Sha1::digest(body))),
+            file_size: Some(body.len() as u64),
+            url: Some(Url {
+                web: format!("{}/files/notes.txt", server.url()),
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+async fn starts_over_when_the_file_changed_since(verify: bool) {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]).verify(verify);
+
+    let old = b"the first version of the notes".to_vec();
+    let new = b"the other version of the notes".to_vec();
+    assert_eq!(old.len(), new.len());
+
+    server.route("/files/notes.txt", Route::ok(old.clone()).hang_up_at(20));
+    assert!(client.download(&notes(&server, &old)).await.is_err());
+    assert!(folder.path().join("notes.txt.part").exists());
+
+    // the same name and size, but humble has a new version of it now
+    server.route("/files/notes.txt", Route::ok(new.clone()).accept_ranges());
+    client.download(&notes(&server, &new)).await.unwrap();
+
+    assert_eq!(fs::read(folder.path().join("notes.txt")).unwrap(), new);
+    assert_eq!(
+        server.requests("/files/notes.txt").last().unwrap().range,
+        None
+    );
+}
+
+#[tokio::test]
+async fn starts_over_when_the_file_changed_since_its_partial_download() {
+    starts_over_when_the_file_changed_since(true).await;
+}
+
+#[tokio::test]
+async fn starts_over_when_the_file_changed_without_verifying() {
+    starts_over_when_the_file_changed_since(false).await;
+}
+
+// /dev/full fails every write with ENOSPC, like a full disk
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn a_full_disk_stops_the_download_and_keeps_the_part_file() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    let part = folder.path().join("book.pdf.part");
+    std::os::unix::fs::symlink("/dev/full", &part).unwrap();
+
+    let result = client
+        .download_url(&format!("{}/files/book.pdf", server.url()))
+        .await;
+
+    match result {
+        Err(ApiError::DiskFull(file)) => assert_eq!(file, "book.pdf"),
+        other => panic!("expected DiskFull, got {:?}", other),
+    }
+    assert!(part.symlink_metadata().is_ok());
+    assert!(!folder.path().join("book.pdf").exists());
+}
+
+#[tokio::test]
+async fn downloads_and_verifies_empty_files() {
+    let server = common::humble();
+    common::serve_order(&server, "zErObYtEgAmEkEy1", "order_empty_file.json");
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    let order = client.get_order("zErObYtEgAmEkEy1").await.unwrap();
+    client.download_order(&order).await.unwrap();
+
+    let path = folder.path().join("empty.txt");
+    assert_eq!(path.metadata().unwrap().len(), 0);
+    assert!(!folder.path().join("empty.txt.part").exists());
+    assert_eq!(client.progress().by_platform()[0].1.files, 1);
+
+    // its hash checks out on disk too, so it's not fetched again
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(server.hits("/files/empty.txt"), 1);
+}
+
+#[tokio::test]
+async fn redownload_reports_a_download_that_failed_its_checksum() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    let path = folder.path().join("book.pdf");
+    let mut corrupt = common::file("book.pdf");
+    corrupt[0] ^= 0xff;
+    fs::write(&path, &corrupt).unwrap();
+    server.route("/files/book.pdf", Route::ok(corrupt.clone()));
+
+    let order = common::order("order.json", &server);
+    let ebook = &order.subproducts[0].downloads[0];
+    let pdf = &ebook.download_struct[1];
+
+    assert!(!client.redownload(ebook, pdf, &path).await.unwrap());
+    assert_eq!(fs::read(&path).unwrap(), corrupt);
+
+    server.route("/files/book.pdf", Route::ok(common::file("book.pdf")));
+
+    assert!(client.redownload(ebook, pdf, &path).await.unwrap());
+    assert_eq!(fs::read(&path).unwrap(), common::file("book.pdf"));
+}
+
+#[tokio::test]
+async fn starts_over_when_the_server_ignores_the_range() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+    let order = client.get_order(GAMEKEY).await.unwrap();
+
+    interrupted_download(&server, &client, &order).await;
+    server.route(
+        "/files/book.epub",
+        Route::ok(common::file("book.epub")).ignore_ranges(),
+    );
+
+    client.download_order(&order).await.unwrap();
+
+    assert_eq!(
+        fs::read(folder.path().join("book.epub")).unwrap(),
+        common::file("book.epub")
+    );
+    let ranges: Vec<_> = server
+        .requests("/files/book.epub")
+        .into_iter()
+        .map(|request| request.range)
+        .collect();
+    assert_eq!(ranges, vec![None, None, Some("bytes=20-".to_owned()), None]);
+}
+
+#[tokio::test]
+async fn carries_on_from_what_was_synced_of_a_preallocated_download() {
+    const SIZE: u64 = 10 * 1024 * 1024;
+    // after the state's first save, 4 MB in, but before its second
+    const HANG_UP_AT: u64 = 6 * 1024 * 1024 + 123;
+
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["linux"]).preallocate(true);
+
+    let mut hasher = Sha1::new();
+    hasher.update((0..SIZE).map(generated_byte).collect::<Vec<_>>());
+    let download = Download {
+        platform: "linux".to_owned(),
+        download_struct: vec![DownloadStruct {
+            sha1: Some(format!("{:x}", hasher.finalize())),
+            file_size: Some(SIZE),
+            url: Some(Url {
+                web: format!("{}/files/large.bin", server.url()),
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    server.route(
+        "/files/large.bin",
+        Route::generated(SIZE).hang_up_at(HANG_UP_AT),
+    );
+    assert!(client.download(&download).await.is_err());
+
+    // preallocated, so its length says nothing about how far it got
+    let part = folder.path().join("large.bin.part");
+    assert_eq!(part.metadata().unwrap().len(), SIZE);
+
+    server.route("/files/large.bin", Route::generated(SIZE).accept_ranges());
+    client.download(&download).await.unwrap();
+
+    assert!(!part.exists());
+    assert_eq!(
+        folder.path().join("large.bin").metadata().unwrap().len(),
+        SIZE
+    );
+
+    // from wherever the first save landed, going by how the chunks fell
+    let range = server.requests("/files/large.bin").pop().unwrap().range;
+    let start: u64 = range
+        .as_deref()
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.strip_suffix('-'))
+        .and_then(|start| start.parse().ok())
+        .unwrap();
+    assert!((4 * 1024 * 1024..HANG_UP_AT).contains(&start), "{}", start);
+}
+
+async fn fails_a_short_byte_range(verify: bool) {
+    const SIZE: u64 = 300;
+
+    let server = common::humble();
+    server.route(
+        "/files/ranged.bin",
+        Route::generated(SIZE).accept_ranges().short_ranges_at(150),
+    );
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["linux"])
+        .download_connections(3)
+        .verify(verify);
+
+    let download = Download {
+        platform: "linux".to_owned(),
+        download_struct: vec![DownloadStruct {
+            file_size: Some(SIZE),
+            url: Some(Url {
+                web: format!("{}/files/ranged.bin", server.url()),
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    // the middle range, 100-199, only gets as far as 150
+    match client.download(&download).await {
+        Err(ApiError::ShortRange {
+            start,
+            expected,
+            written,
+        }) => assert_eq!((start, expected, written), (100, 100, 50)),
+        other => panic!("expected ShortRange, got {:?}", other),
+    }
+    assert!(!folder.path().join("ranged.bin").exists());
+}
+
+#[tokio::test]
+async fn fails_a_short_byte_range_when_verifying() {
+    fails_a_short_byte_range(true).await;
+}
+
+#[tokio::test]
+async fn fails_a_short_byte_range_without_verifying() {
+    fails_a_short_byte_range(false).await;
+}
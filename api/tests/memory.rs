@@ -0,0 +1,181 @@
+//! Checks that files much bigger than the memory they're allowed are
+//! downloaded and verified without ever being held in memory whole, even
+//! when writing falls behind the network, by counting every allocation in
+//! this test binary.
+
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use common::{Route, TempDir};
+use futures::future;
+use hb_api::types::{Download, DownloadStruct, Url};
+use hb_test_support::generated_byte;
+use sha1::{Digest, Sha1};
+use tokio::time::delay_for;
+
+const FILE_SIZE: u64 = 64 * 1024 * 1024;
+// network and hash buffers, with plenty of room to spare, but far short of
+// the file
+const MEMORY_LIMIT: usize = 8 * 1024 * 1024;
+// long enough for the server to fill every buffer between it and the writer
+const STALL: Duration = Duration::from_secs(2);
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+            grew(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn grew(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::SeqCst) + size;
+    PEAK.fetch_max(allocated, Ordering::SeqCst);
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+// the counts are for the whole process, so only one test measures at a time
+static MEASURING: Mutex<()> = Mutex::new(());
+
+fn run_alone<F: Future>(future: F) -> F::Output {
+    let _measuring = MEASURING.lock().unwrap_or_else(|e| e.into_inner());
+
+    tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// Runs `future`, returning its output and the most memory that was
+/// allocated on top of what already was when it started.
+async fn peak_during<F: Future>(future: F) -> (F::Output, usize) {
+    let baseline = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+
+    let output = future.await;
+
+    (output, PEAK.load(Ordering::SeqCst).saturating_sub(baseline))
+}
+
+/// A download of a large generated file, with its real sha1.
+fn large_download(server: &common::MockServer) -> Download {
+    server.route("/files/large.bin", Route::generated(FILE_SIZE));
+
+    let mut hasher = Sha1::new();
+    let mut buffer = Vec::with_capacity(64 * 1024);
+    for start in (0..FILE_SIZE).step_by(64 * 1024) {
+        buffer.clear();
+        buffer.extend((start..FILE_SIZE.min(start + 64 * 1024)).map(generated_byte));
+        hasher.update(&buffer);
+    }
+
+    Download {
+        platform: "linux".to_owned(),
+        download_struct: vec![DownloadStruct {
+            sha1: Some(format!("{:x}", hasher.finalize())),
+            file_size: Some(FILE_SIZE),
+            url: Some(Url {
+                web: format!("{}/files/large.bin", server.url()),
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn downloads_and_verifies_large_files_in_bounded_memory() {
+    run_alone(async {
+        let server = common::MockServer::start();
+        let download = large_download(&server);
+        let folder = TempDir::new();
+        let client = common::client(&server, folder.path(), &["linux"]);
+
+        let (result, peak) = peak_during(client.download(&download)).await;
+        result.unwrap();
+
+        let path = folder.path().join("large.bin");
+        assert_eq!(path.metadata().unwrap().len(), FILE_SIZE);
+        assert!(peak < MEMORY_LIMIT, "download peaked at {} bytes", peak);
+
+        // the file is there now, so this time it's only hashed
+        let (result, peak) = peak_during(client.download(&download)).await;
+        result.unwrap();
+
+        assert_eq!(server.hits("/files/large.bin"), 1);
+        assert!(peak < MEMORY_LIMIT, "verifying peaked at {} bytes", peak);
+    });
+}
+
+#[test]
+fn stalled_writes_dont_buffer_the_download_in_memory() {
+    run_alone(async {
+        let server = common::MockServer::start();
+        let download = large_download(&server);
+        let folder = TempDir::new();
+        let client = common::client(&server, folder.path(), &["linux"]);
+
+        // the pause flag holds the write loop between chunks like a disk that
+        // can't keep up, while the server goes on sending as fast as it can
+        let paused = client.pause_flag();
+        let progress = client.progress();
+        paused.store(true, Ordering::SeqCst);
+
+        let stalls = async {
+            delay_for(STALL).await;
+            paused.store(false, Ordering::SeqCst);
+
+            while progress.downloaded() < FILE_SIZE / 2 {
+                delay_for(Duration::from_millis(10)).await;
+            }
+
+            paused.store(true, Ordering::SeqCst);
+            delay_for(STALL).await;
+            paused.store(false, Ordering::SeqCst);
+        };
+
+        let ((result, peak), ()) =
+            future::join(peak_during(client.download(&download)), stalls).await;
+        result.unwrap();
+
+        assert_eq!(
+            folder.path().join("large.bin").metadata().unwrap().len(),
+            FILE_SIZE
+        );
+        assert!(peak < MEMORY_LIMIT, "download peaked at {} bytes", peak);
+    });
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::{Route, TempDir, GAMEKEY};
+use hb_api::ApiError;
+
+#[tokio::test]
+async fn orders_that_404_arent_found() {
+    let server = common::humble();
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    match client.get_order("rEfUnDeDgAmEkEy1").await {
+        Err(ApiError::OrderNotFound { gamekey }) => assert_eq!(gamekey, "rEfUnDeDgAmEkEy1"),
+        result => panic!("expected OrderNotFound, got {:?}", result.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn rejected_sessions_are_unauthorized() {
+    let server = common::humble();
+    server.route(&format!("/api/v1/order/{}", GAMEKEY), Route::status(401));
+    let folder = TempDir::new();
+    let client = common::client(&server, folder.path(), &["ebook"]);
+
+    assert!(matches!(
+        client.get_order(GAMEKEY).await,
+        Err(ApiError::Unauthorized)
+    ));
+}
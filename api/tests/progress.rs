@@ -0,0 +1,84 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Route, TempDir};
+use futures::future;
+use hb_api::{
+    types::{Download, DownloadStruct, Url},
+    ProgressEvent,
+};
+use tokio::sync::mpsc;
+
+const FILE_SIZE: u64 = 8 * 1024 * 1024;
+const CAPACITY: usize = 4;
+
+#[tokio::test]
+async fn slow_receivers_get_fewer_progress_events_adding_up_to_the_file() {
+    let server = common::MockServer::start();
+    server.route("/files/large.bin", Route::generated(FILE_SIZE));
+    let folder = TempDir::new();
+
+    let (sender, mut receiver) = mpsc::channel(CAPACITY);
+    let client = common::client(&server, folder.path(), &["linux"]).progress_events(sender);
+
+    let download = Download {
+        platform: "linux".to_owned(),
+        download_struct: vec![DownloadStruct {
+            file_size: Some(FILE_SIZE),
+            url: Some(Url {
+                web: format!("{}/files/large.bin", server.url()),
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let receiving = async {
+        let mut events = Vec::new();
+
+        loop {
+            let event = receiver.recv().await.unwrap();
+            let done = matches!(event, ProgressEvent::Done { .. });
+            events.push(event);
+
+            if done {
+                return events;
+            }
+
+            tokio::time::delay_for(Duration::from_millis(20)).await;
+        }
+    };
+
+    let (result, events) = future::join(client.download(&download), receiving).await;
+    result.unwrap();
+
+    assert_eq!(
+        events.first(),
+        Some(&ProgressEvent::Started {
+            gamekey: None,
+            file: "large.bin".to_owned(),
+            size: Some(FILE_SIZE),
+        })
+    );
+    assert_eq!(
+        events.last(),
+        Some(&ProgressEvent::Done {
+            gamekey: None,
+            file: "large.bin".to_owned(),
+            valid: true,
+        })
+    );
+
+    let progress: Vec<u64> = events
+        .iter()
+        .filter_map(|event| match event {
+            ProgressEvent::Progress { bytes, .. } => Some(*bytes),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(progress.iter().sum::<u64>(), FILE_SIZE);
+    // one per network chunk would be hundreds
+    assert!(progress.len() < 100, "{} progress events", progress.len());
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use common::MockServer;
+
+fn platforms(names: &[&str]) -> Vec<String> {
+    names.iter().map(|&name| name.to_owned()).collect()
+}
+
+#[test]
+fn total_size_sums_the_files_of_the_given_platforms() {
+    let order = common::order("order.json", &MockServer::start());
+
+    assert_eq!(order.total_size(&platforms(&["ebook"])), Some(45 + 24));
+    assert_eq!(
+        order.total_size(&platforms(&["ebook", "linux", "windows"])),
+        Some(45 + 24 + 24 + 29)
+    );
+    assert_eq!(order.total_size(&platforms(&["mac"])), Some(0));
+}
+
+#[test]
+fn total_size_is_unknown_when_a_file_has_no_size() {
+    let order = common::order("order_empty_web.json", &MockServer::start());
+
+    assert_eq!(order.total_size(&platforms(&["ebook"])), None);
+    assert_eq!(order.total_size(&platforms(&["linux"])), Some(0));
+}
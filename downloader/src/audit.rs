@@ -0,0 +1,79 @@
+use std::{error::Error, fs, path::Path};
+
+use hb_api::HBClient;
+use sha1::{Digest, Sha1};
+
+const HEADER: &str = "# hb-downloader library manifest v1\n\
+    # gamekey\ttitle\tplatform\tfile\tsize\tsha1\tmd5\n";
+
+/// Writes every file in the library with its size and hashes to `out`, one
+/// sorted line per file so the same library always gives the same bytes, and
+/// its sha1 next to it in `<out>.sha1`, which `sha1sum -c` understands.
+pub async fn run(client: &HBClient, out: &Path) -> Result<(), Box<dyn Error>> {
+    let mut lines = Vec::new();
+
+    for order_item in client.list_orders().await? {
+        let order = client.get_order(&order_item.gamekey).await?;
+
+        for subproduct in &order.subproducts {
+            let title = subproduct
+                .machine_name
+                .as_deref()
+                .unwrap_or(&subproduct.human_name);
+
+            for download in &subproduct.downloads {
+                for file in &download.download_struct {
+                    lines.push(
+                        [
+                            order.gamekey.as_str(),
+                            title,
+                            &download.platform,
+                            &file.file_name().unwrap_or_default(),
+                            &file
+                                .file_size
+                                .map(|size| size.to_string())
+                                .unwrap_or_default(),
+                            file.sha1.as_deref().unwrap_or(""),
+                            file.md5.as_deref().unwrap_or(""),
+                        ]
+                        .iter()
+                        .map(|field| field.replace(['\t', '\n'], " "))
+                        .collect::<Vec<_>>()
+                        .join("\t"),
+                    );
+                }
+            }
+        }
+    }
+
+    lines.sort();
+    lines.dedup();
+
+    let mut manifest = HEADER.to_owned();
+    for line in &lines {
+        manifest.push_str(line);
+        manifest.push('\n');
+    }
+
+    fs::write(out, &manifest)?;
+
+    let checksum = format!("{:x}", Sha1::digest(manifest.as_bytes()));
+
+    let mut checksum_path = out.as_os_str().to_owned();
+    checksum_path.push(".sha1");
+
+    let name = out
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    fs::write(&checksum_path, format!("{}  {}\n", checksum, name))?;
+
+    println!(
+        "wrote {} files to {}, sha1 {}",
+        lines.len(),
+        out.display(),
+        checksum
+    );
+
+    Ok(())
+}
@@ -1,16 +1,80 @@
-use std::collections::{HashMap, HashSet};
+use std::{collections::HashMap, env, fs, path::PathBuf};
 
 use config::{Config, ConfigError, File, FileFormat};
-use serde::{self, Deserialize};
+use directories::ProjectDirs;
+use hb_api::{FilenameTemplate, FolderStructure, HttpOptions, Layout, SizePreference};
+use serde::{self, de::Error, Deserialize, Deserializer};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
+    #[serde(deserialize_with = "read_headers")]
     pub headers: HashMap<String, String>,
-    pub platforms: HashSet<String>,
+    pub platforms: Vec<String>,
+    #[serde(flatten)]
+    pub http: HttpOptions,
+    pub folder_structure: FolderStructure,
+    pub layout: Layout,
+    #[serde(default)]
+    pub platform_folders: HashMap<String, PathBuf>,
+    #[serde(default)]
+    pub api_params: HashMap<String, String>,
+    #[serde(default)]
+    pub host_rewrites: HashMap<String, String>,
+    pub session_folder: bool,
+    pub check_disk_space: bool,
+    pub flatten_single: bool,
+    pub filename_template: Option<FilenameTemplate>,
+    pub tag_extensionless: bool,
+    pub fix_extensions: bool,
+    pub audio_format_priority: Vec<String>,
+    pub ebook_format_priority: Vec<String>,
+    pub size_preference: Option<SizePreference>,
+    pub allowed_extensions: Vec<String>,
+    pub blocked_extensions: Vec<String>,
+    pub keep_all_platforms: Vec<String>,
+    pub require_auth: bool,
+    pub workers: usize,
+    pub order_concurrency: usize,
+    pub preallocate: bool,
+    pub download_connections: usize,
+    pub max_connections_per_host: Option<usize>,
+    pub retries: u32,
+    pub retry_delay_ms: u64,
+    pub retry_budget: Option<u64>,
+    pub check_file_size: bool,
+    pub verify_all_hashes: bool,
+    pub quarantine_on_failure: bool,
+    pub verify_checkpoint_hours: u64,
+    pub notify_command: Option<String>,
+    pub post_download_command: Option<String>,
+    pub notify_webhook: Option<String>,
 }
 
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
+        Self::load(None)
+    }
+
+    /// Like `new`, but with the session cookie given directly, replacing any
+    /// Cookie header from the config files. A bare value is taken to be the
+    /// `_simpleauth_sess` cookie.
+    pub fn with_cookie(cookie: &str) -> Result<Self, ConfigError> {
+        let cookie = cookie.trim();
+
+        if cookie.is_empty() {
+            return Err(ConfigError::Message(
+                "no session cookie was given".to_owned(),
+            ));
+        }
+
+        if cookie.contains('=') {
+            Self::load(Some(cookie.to_owned()))
+        } else {
+            Self::load(Some(format!("_simpleauth_sess={}", cookie)))
+        }
+    }
+
+    fn load(cookie: Option<String>) -> Result<Self, ConfigError> {
         let mut s = Config::new();
 
         s.merge(File::from_str(
@@ -18,8 +82,222 @@ impl Settings {
             FileFormat::Toml,
         ))?;
 
+        if let Some(path) = system_config_path() {
+            s.merge(File::from(path).required(false))?;
+        }
+
+        if let Some(path) = user_config_path() {
+            s.merge(File::from(path).required(false))?;
+        }
+
         s.merge(File::with_name("config").required(false))?;
 
-        s.try_into()
+        let mut merged: toml::Value = s.try_into()?;
+        interpolate(&mut merged, "")?;
+
+        let mut settings: Self = merged
+            .try_into()
+            .map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+
+        if let Some(cookie) = cookie {
+            settings
+                .headers
+                .retain(|name, _| !name.eq_ignore_ascii_case("cookie"));
+            settings.headers.insert("cookie".to_owned(), cookie);
+        }
+
+        settings.check_auth()?;
+
+        Ok(settings)
+    }
+
+    /// Running without a session cookie only gets confusing errors back from
+    /// humble, so point that out up front.
+    fn check_auth(&self) -> Result<(), ConfigError> {
+        let has_auth = self.headers.iter().any(|(name, value)| {
+            let name = name.to_lowercase();
+            (name == "cookie" || name == "authorization") && !value.trim().is_empty()
+        });
+
+        if has_auth {
+            return Ok(());
+        }
+
+        let message = "it looks like you haven't configured your session cookie, \
+            set the Cookie header in your config to \
+            \"_simpleauth_sess=<your session cookie>\"";
+
+        if self.require_auth {
+            return Err(ConfigError::Message(message.to_owned()));
+        }
+
+        eprintln!("warning: {}", message);
+
+        Ok(())
+    }
+}
+
+/// Machine-wide config for defaults shared by every user, such as proxy
+/// settings. The user and working directory configs override it.
+pub fn system_config_path() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("ProgramData")
+            .map(|dir| PathBuf::from(dir).join("hb-downloader").join("config.toml"))
+    } else {
+        Some(PathBuf::from("/etc/hb-downloader/config.toml"))
+    }
+}
+
+/// Per-user config, e.g. `$XDG_CONFIG_HOME/hb-downloader/config.toml` on linux.
+/// A `config.toml` in the working directory still overrides it.
+pub fn user_config_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "hb-downloader").map(|dirs| dirs.config_dir().join("config.toml"))
+}
+
+// run by a shell, which expands variables itself, including the ones set for
+// the command that aren't around yet when the config is read
+const SHELL_COMMANDS: &[&str] = &["notify_command", "post_download_command"];
+
+/// Replaces `${NAME}` in every string setting with that environment variable,
+/// so secrets and machine-specific paths can stay out of the file. `$$` is a
+/// literal `$`. Shell commands are left for the shell to expand.
+fn interpolate(value: &mut toml::Value, key: &str) -> Result<(), ConfigError> {
+    match value {
+        toml::Value::String(s) => *s = expand(s, key)?,
+        toml::Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                interpolate(value, &format!("{}[{}]", key, i))?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                if SHELL_COMMANDS.contains(&name.as_str()) {
+                    continue;
+                }
+
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                interpolate(value, &key)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn expand(s: &str, key: &str) -> Result<String, ConfigError> {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| {
+                ConfigError::Message(format!("unclosed ${{ in config value {}", key))
+            })?;
+            let name = &after[..end];
+
+            match env::var(name) {
+                Ok(value) => expanded.push_str(&value),
+                Err(_) => {
+                    return Err(ConfigError::Message(format!(
+                        "config value {} uses ${{{}}}, which isn't set",
+                        key, name
+                    )))
+                }
+            }
+
+            rest = &after[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HeaderValue {
+    Inline(String),
+    File { file: PathBuf },
+}
+
+/// Accepts either a plain string or `{ file = "path" }` for each header, so
+/// secrets like the session cookie can live in a separate, locked-down file.
+fn read_headers<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, HeaderValue>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, value)| match value {
+            HeaderValue::Inline(value) => Ok((name, value)),
+            HeaderValue::File { file } => match fs::read_to_string(&file) {
+                Ok(contents) => Ok((name, contents.trim().to_owned())),
+                Err(e) => Err(D::Error::custom(format!(
+                    "couldn't read header {} from {}: {}",
+                    name,
+                    file.display(),
+                    e
+                ))),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_shell_commands_to_the_shell() {
+        env::set_var("HB_TEST_FOLDER", "/srv/humble");
+        env::set_var("HB_TEST_COOKIE", "_simpleauth_sess=abc");
+
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            notify_command = "notify-send \"${HB_STATUS}\""
+            post_download_command = "echo ${HB_UNSET} {path}"
+
+            [platform_folders]
+            windows = "${HB_TEST_FOLDER}/windows"
+
+            [headers]
+            Cookie = "${HB_TEST_COOKIE}"
+            "#,
+        )
+        .unwrap();
+
+        interpolate(&mut value, "").unwrap();
+
+        assert_eq!(
+            value["platform_folders"]["windows"].as_str(),
+            Some("/srv/humble/windows")
+        );
+        assert_eq!(
+            value["headers"]["Cookie"].as_str(),
+            Some("_simpleauth_sess=abc")
+        );
+        assert_eq!(
+            value["notify_command"].as_str(),
+            Some("notify-send \"${HB_STATUS}\"")
+        );
+        assert_eq!(
+            value["post_download_command"].as_str(),
+            Some("echo ${HB_UNSET} {path}")
+        );
     }
 }
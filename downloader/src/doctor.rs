@@ -0,0 +1,78 @@
+use std::{fs, path::Path};
+
+use hb_api::HBClient;
+
+use crate::{config::Settings, describe};
+
+const PROBE_FILE: &str = ".hb-downloader-doctor";
+
+pub async fn run(download_folder: &Path) -> bool {
+    let config = match Settings::new() {
+        Ok(config) => {
+            report("config", Ok("parsed".to_owned()));
+            config
+        }
+        Err(e) => {
+            report("config", Err(describe(&e)));
+            return false;
+        }
+    };
+
+    let mut passed = true;
+
+    passed &= report("download folder", check_writable(download_folder));
+
+    passed &= report(
+        "platforms",
+        if config.platforms.is_empty() {
+            Err("no platforms configured, nothing will be downloaded".to_owned())
+        } else {
+            Ok(config.platforms.join(", "))
+        },
+    );
+
+    let client = HBClient::new(
+        download_folder.to_path_buf(),
+        config.headers,
+        config.platforms,
+        &config.http,
+    );
+
+    passed &= report(
+        "session",
+        match client {
+            Ok(client) => match client.list_orders().await {
+                Ok(orders) => Ok(format!("authenticated, {} orders visible", orders.len())),
+                Err(e) => Err(format!(
+                    "{} (check the Cookie header in your config)",
+                    describe(&e)
+                )),
+            },
+            Err(e) => Err(describe(&e)),
+        },
+    );
+
+    passed
+}
+
+fn check_writable(folder: &Path) -> Result<String, String> {
+    let probe = folder.join(PROBE_FILE);
+
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map(|_| format!("{} is writable", folder.display()))
+        .map_err(|e| format!("{} is not writable: {}", folder.display(), e))
+}
+
+fn report(check: &str, result: Result<String, String>) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("PASS {}: {}", check, detail);
+            true
+        }
+        Err(detail) => {
+            println!("FAIL {}: {}", check, detail);
+            false
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use hb_api::{types::Order, HBClient};
+use serde::Serialize;
+
+pub enum ExportFormat {
+    Csv,
+    Json,
+    /// One json object per line, written as each order is fetched.
+    JsonLines,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "jsonl" => Ok(ExportFormat::JsonLines),
+            _ => Err(format!(
+                "unknown export format {}, expected csv, json or jsonl",
+                format
+            )),
+        }
+    }
+}
+
+/// One file in the library, regardless of the configured platform filter.
+#[derive(Serialize)]
+struct Row<'a> {
+    gamekey: &'a str,
+    bundle: &'a str,
+    subproduct: &'a str,
+    machine_name: Option<&'a str>,
+    platform: &'a str,
+    filename: Option<String>,
+    size: Option<u64>,
+    sha1: Option<&'a str>,
+    md5: Option<&'a str>,
+}
+
+/// Writes the catalog to `out`, or to stdout if it's `-`.
+pub async fn run(
+    client: &HBClient,
+    format: ExportFormat,
+    out: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let to_stdout = out == Path::new("-");
+    let file: Box<dyn Write> = if to_stdout {
+        Box::new(io::stdout())
+    } else {
+        Box::new(File::create(out)?)
+    };
+    let mut file = BufWriter::new(file);
+
+    let count = match format {
+        ExportFormat::JsonLines => {
+            let mut count = 0;
+
+            // nothing is kept past its order, however big the library
+            for order_item in client.list_orders().await? {
+                let order = client.get_order(&order_item.gamekey).await?;
+
+                for row in rows(&order) {
+                    serde_json::to_writer(&mut file, &row)?;
+                    file.write_all(b"\n")?;
+                    count += 1;
+                }
+
+                file.flush()?;
+            }
+
+            count
+        }
+        format => {
+            let mut orders = Vec::new();
+
+            for order_item in client.list_orders().await? {
+                orders.push(client.get_order(&order_item.gamekey).await?);
+            }
+
+            let rows: Vec<_> = orders.iter().flat_map(rows).collect();
+
+            match format {
+                ExportFormat::Csv => {
+                    let mut writer = csv::Writer::from_writer(&mut file);
+
+                    for row in &rows {
+                        writer.serialize(row)?;
+                    }
+
+                    writer.flush()?;
+                }
+                _ => serde_json::to_writer_pretty(&mut file, &rows)?,
+            }
+
+            file.flush()?;
+            rows.len()
+        }
+    };
+
+    // stdout is the catalog, so the summary goes elsewhere
+    if to_stdout {
+        eprintln!("exported {} files", count);
+    } else {
+        println!("exported {} files to {}", count, out.display());
+    }
+
+    Ok(())
+}
+
+fn rows(order: &Order) -> Vec<Row<'_>> {
+    let mut rows = Vec::new();
+
+    for subproduct in &order.subproducts {
+        for download in &subproduct.downloads {
+            for file in &download.download_struct {
+                rows.push(Row {
+                    gamekey: &order.gamekey,
+                    bundle: &order.product.human_name,
+                    subproduct: &subproduct.human_name,
+                    machine_name: subproduct.machine_name.as_deref(),
+                    platform: &download.platform,
+                    filename: file.file_name(),
+                    size: file.file_size,
+                    sha1: file.sha1.as_deref(),
+                    md5: file.md5.as_deref(),
+                });
+            }
+        }
+    }
+
+    rows
+}
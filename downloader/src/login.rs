@@ -0,0 +1,106 @@
+use std::{error::Error, fs, io, path::Path};
+
+use hb_api::HBClient;
+
+use crate::config::{user_config_path, Settings};
+
+const INSTRUCTIONS: &str = "\
+To find your session cookie:
+  1. log in to https://www.humblebundle.com in your browser
+  2. open the developer tools (F12) and go to the storage or application tab
+  3. under cookies for https://www.humblebundle.com, copy the value of
+     _simpleauth_sess
+";
+
+/// Walks the user through finding their session cookie, then checks the one
+/// they paste against humble and saves it to the user config.
+pub async fn run(download_folder: &Path) -> Result<(), Box<dyn Error>> {
+    let config_path = user_config_path().ok_or("couldn't work out where the user config goes")?;
+
+    print!("{}", INSTRUCTIONS);
+    println!("Paste the cookie value and press enter:");
+
+    let mut cookie = String::new();
+    io::stdin().read_line(&mut cookie)?;
+
+    let config = Settings::with_cookie(&cookie)?;
+    let cookie = config.headers["cookie"].clone();
+
+    let client = HBClient::new(
+        download_folder.to_path_buf(),
+        config.headers,
+        config.platforms,
+        &config.http,
+    )?;
+    let orders = client.list_orders().await?;
+
+    save_cookie(&config_path, &cookie)?;
+
+    println!(
+        "logged in, {} orders visible. saved the cookie to {}",
+        orders.len(),
+        config_path.display()
+    );
+
+    Ok(())
+}
+
+/// Sets the Cookie header in the config file, keeping everything else in it.
+fn save_cookie(path: &Path, cookie: &str) -> Result<(), Box<dyn Error>> {
+    let mut config = match fs::read_to_string(path) {
+        Ok(contents) => contents.parse::<toml::Value>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Value::Table(Default::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let table = config
+        .as_table_mut()
+        .ok_or("config file isn't a toml table")?;
+    let headers = table
+        .entry("headers")
+        .or_insert_with(|| toml::Value::Table(Default::default()))
+        .as_table_mut()
+        .ok_or("headers in the config file isn't a table")?;
+
+    let old: Vec<String> = headers
+        .keys()
+        .filter(|name| name.eq_ignore_ascii_case("cookie"))
+        .cloned()
+        .collect();
+
+    for name in old {
+        headers.remove(&name);
+    }
+
+    headers.insert("Cookie".to_owned(), toml::Value::String(cookie.to_owned()));
+
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder)?;
+    }
+
+    write_private(path, &toml::to_string(&config)?)?;
+
+    Ok(())
+}
+
+/// Writes a file only the current user can read, since it holds the session.
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt, os::unix::fs::PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // an existing file keeps its old mode otherwise
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
@@ -1,11 +1,61 @@
-use std::{env, error::Error};
+use std::{
+    collections::{BTreeMap, HashSet},
+    env,
+    error::Error,
+    fmt, fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use clap::Clap;
-use hb_api::HBClient;
+use hb_api::{ApiError, HBClient, Progress, ProgressEvent, RetryPolicy};
+use tokio::{
+    sync::mpsc::{self, Receiver},
+    time,
+};
 
-use crate::config::Settings;
+use crate::{config::Settings, export::ExportFormat};
 
+mod audit;
 mod config;
+mod doctor;
+mod export;
+mod login;
+mod notify;
+mod platform_report;
+mod print_urls;
+mod reconcile;
+mod show;
+mod sync;
+mod verify;
+mod watch;
+
+// process exit codes, so scripts can tell failures apart without parsing output
+const EXIT_OK: i32 = 0;
+// anything not covered by a more specific code below
+const EXIT_FAILURE: i32 = 1;
+// humble rejected the session, usually a missing or expired Cookie header
+const EXIT_AUTH: i32 = 2;
+// the run finished but some orders failed to download
+const EXIT_PARTIAL: i32 = 3;
+// the config file couldn't be read or parsed, or has a header that can't be sent
+const EXIT_CONFIG: i32 = 4;
+// interrupted with ctrl-c
+const EXIT_CANCELLED: i32 = 130;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+// progress going to a log file or pipe is printed less often, to keep cron
+// and ci logs short
+const LOG_PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+// progress events waiting to be tallied into the per-file lines
+const EVENT_CAPACITY: usize = 64;
+const MB: u64 = 1024 * 1024;
 
 #[derive(Clap)]
 #[clap(
@@ -13,6 +63,59 @@ mod config;
     author = "Simon Dickson <simonhdickson@users.noreply.github.com>"
 )]
 struct Opts {
+    /// Read the session cookie from the first line of stdin instead of the
+    /// config, to keep it out of files and process arguments
+    #[clap(long)]
+    cookie_stdin: bool,
+    /// Download only this platform for this run, instead of the configured ones
+    #[clap(long)]
+    platform: Option<String>,
+    /// Platform to use for titles without a --platform download, in order
+    #[clap(long, requires = "platform", number_of_values = 1)]
+    fallback: Vec<String>,
+    /// Download every platform for this run, ignoring the configured platforms
+    #[clap(long)]
+    all_platforms: bool,
+    /// Platforms to download for this run, comma separated, replacing the
+    /// configured platforms entirely, e.g. --platforms windows,linux
+    #[clap(
+        long,
+        use_delimiter = true,
+        conflicts_with_all = &["platform", "all-platforms"]
+    )]
+    platforms: Vec<String>,
+    /// Only download titles in this category, e.g. game, can be repeated.
+    /// Titles humble gives no category, directly or through their order, are
+    /// skipped
+    #[clap(long, number_of_values = 1)]
+    category: Vec<String>,
+    /// Ebook format to download ahead of the configured ebook_format_priority
+    #[clap(long)]
+    prefer_format: Option<String>,
+    /// Accept existing files whose first and last MB still match their last
+    /// full check instead of hashing them whole. Probabilistic: corruption in
+    /// the middle of a file isn't caught, run without it now and then
+    #[clap(long)]
+    quick: bool,
+    /// Don't hash files, only check their size. Faster, but corrupted
+    /// downloads of the right length aren't caught
+    #[clap(long)]
+    no_verify: bool,
+    /// Stop starting new files once this many bytes have been downloaded in
+    /// this run. Run again later to get the rest
+    #[clap(long)]
+    max_total_bytes: Option<u64>,
+    /// Skip files listed in this file, one file name or sha1 or md5 hash per
+    /// line, e.g. ones already downloaded on another machine. sha1sum and
+    /// md5sum output works too
+    #[clap(long)]
+    have_list: Option<PathBuf>,
+    /// After downloading, print how many files and bytes came from each platform
+    #[clap(long)]
+    platform_report: bool,
+    /// Report failures on stderr as json lines instead of prose
+    #[clap(long)]
+    json_errors: bool,
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
@@ -20,19 +123,319 @@ struct Opts {
 #[derive(Clap)]
 enum SubCommand {
     ListOrders,
-    DownloadAll,
-    DownloadOrder { gamekey: String },
+    DownloadAll {
+        /// Only download each title once, from the newest order that has it
+        #[clap(long)]
+        newest_only: bool,
+        /// Skip orders that downloaded completely on an earlier --skip-complete
+        /// run, as long as the files they should have haven't changed
+        #[clap(long)]
+        skip_complete: bool,
+        /// Skip files humble hasn't updated since the last fully successful
+        /// --incremental run, if they're already downloaded
+        #[clap(long)]
+        incremental: bool,
+        /// How many files to download at once, overriding the workers setting
+        #[clap(short = 'j', long)]
+        workers: Option<usize>,
+    },
+    /// Run DownloadAll every few minutes until interrupted, to pick up new
+    /// purchases. Orders that completed before are skipped while unchanged
+    Watch {
+        /// Minutes to wait between syncs
+        #[clap(long, default_value = "60")]
+        interval: u64,
+        /// How many files to download at once, overriding the workers setting
+        #[clap(short = 'j', long)]
+        workers: Option<usize>,
+    },
+    DownloadOrder {
+        gamekey: String,
+        /// Only download the title with this machine name or name
+        #[clap(long)]
+        subproduct: Option<String>,
+        /// How many files to download at once, overriding the workers setting
+        #[clap(short = 'j', long)]
+        workers: Option<usize>,
+    },
+    /// Print the order's json as humble returned it
+    RawOrder {
+        gamekey: String,
+    },
+    /// Print a table of an order's files without downloading anything
+    ShowOrder {
+        gamekey: String,
+    },
+    DownloadUrl {
+        url: String,
+    },
+    /// Write every file in the library to a csv, json or json lines catalog,
+    /// or to stdout with `-`. jsonl writes each order's files as it goes
+    Export {
+        #[clap(long, default_value = "csv")]
+        format: ExportFormat,
+        out: PathBuf,
+    },
+    /// Write a sorted manifest of every file in the library with its size and
+    /// hashes, and the manifest's own sha1 next to it, to compare over time
+    Audit {
+        out: PathBuf,
+    },
+    /// Print freshly signed download urls for one order or every order, one
+    /// per line, to hand to another downloader
+    PrintUrls {
+        gamekey: Option<String>,
+        /// Print aria2c input file entries, with where to save each file and
+        /// its checksum
+        #[clap(long)]
+        aria2: bool,
+    },
+    /// Report library files that are missing, stale or corrupt in the download
+    /// folder, and files there that aren't in the library
+    Reconcile,
+    /// Check files you already have against an order's hashes by file name,
+    /// without downloading anything unless --repair is given
+    VerifyPath {
+        gamekey: String,
+        path: PathBuf,
+        /// Download files that fail the check again, in place
+        #[clap(long)]
+        repair: bool,
+    },
+    Doctor,
+    /// Explain how to find your session cookie, then check and save it
+    Login,
+}
+
+#[derive(Debug)]
+struct PartialFailure {
+    failed: usize,
 }
 
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} orders failed to download", self.failed)
+    }
+}
+
+impl Error for PartialFailure {}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() {
+    env_logger::init();
+
     let opts: Opts = Opts::parse();
 
-    let config = Settings::new()?;
+    let json_errors = opts.json_errors;
+
+    let code = match run(opts).await {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            report_error(json_errors, None, &*e);
+            exit_code(&*e)
+        }
+    };
+
+    process::exit(code);
+}
 
+fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    if error.is::<::config::ConfigError>() {
+        EXIT_CONFIG
+    } else if error.is::<PartialFailure>() {
+        EXIT_PARTIAL
+    } else {
+        match error.downcast_ref::<ApiError>() {
+            Some(ApiError::Unauthorized) => EXIT_AUTH,
+            Some(ApiError::InvalidHeader(_)) => EXIT_CONFIG,
+            Some(ApiError::Cancelled) => EXIT_CANCELLED,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+/// Prints a failure to stderr, either as prose or as a single json object
+/// with the gamekey, file, kind and message.
+fn report_error(json: bool, gamekey: Option<&str>, error: &(dyn Error + 'static)) {
+    if !json {
+        match gamekey {
+            Some(gamekey) => eprintln!("failed to download order {}: {}", gamekey, describe(error)),
+            None => eprintln!("error: {}", describe(error)),
+        }
+        return;
+    }
+
+    let api_error = error.downcast_ref::<ApiError>();
+
+    let kind = if error.is::<::config::ConfigError>() {
+        "config"
+    } else if error.is::<PartialFailure>() {
+        "partial"
+    } else {
+        api_error.map_or("other", ApiError::kind)
+    };
+
+    let line = serde_json::json!({
+        "gamekey": gamekey,
+        "file": api_error.and_then(ApiError::file),
+        "kind": kind,
+        "message": describe(error),
+    });
+
+    eprintln!("{}", line);
+}
+
+fn describe(error: &dyn Error) -> String {
+    match error.source() {
+        Some(source) => format!("{}: {}", error, source),
+        None => error.to_string(),
+    }
+}
+
+async fn run(opts: Opts) -> Result<(), Box<dyn Error>> {
     let path = env::current_dir()?;
 
-    let client = HBClient::new(path, config.headers, config.platforms);
+    if let SubCommand::Login = opts.subcmd {
+        return login::run(&path).await;
+    }
+
+    if let SubCommand::Doctor = opts.subcmd {
+        if !doctor::run(&path).await {
+            process::exit(EXIT_FAILURE);
+        }
+
+        return Ok(());
+    }
+
+    let mut config = if opts.cookie_stdin {
+        let mut cookie = String::new();
+        io::stdin().read_line(&mut cookie)?;
+        Settings::with_cookie(&cookie)?
+    } else {
+        Settings::new()?
+    };
+
+    if let Some(format) = opts.prefer_format {
+        config.ebook_format_priority.insert(0, format);
+    }
+
+    if !opts.platforms.is_empty() {
+        config.platforms = opts
+            .platforms
+            .iter()
+            .map(|platform| platform.trim().to_owned())
+            .filter(|platform| !platform.is_empty())
+            .collect();
+    }
+
+    let workers = match opts.subcmd {
+        SubCommand::DownloadAll { workers, .. }
+        | SubCommand::DownloadOrder { workers, .. }
+        | SubCommand::Watch { workers, .. } => workers.unwrap_or(config.workers),
+        _ => config.workers,
+    };
+
+    let have = match &opts.have_list {
+        Some(have_list) => read_have_list(have_list)?,
+        None => HashSet::new(),
+    };
+
+    let session = if config.session_folder {
+        Some(session_date(SystemTime::now()))
+    } else {
+        None
+    };
+
+    let client = HBClient::new(path.clone(), config.headers, config.platforms, &config.http)?
+        .folder_structure(config.folder_structure)
+        .layout(config.layout)
+        .platform_folders(config.platform_folders)
+        .api_params(config.api_params)
+        .host_rewrites(config.host_rewrites)
+        .check_disk_space(config.check_disk_space)
+        .flatten_single(config.flatten_single)
+        .filename_template(config.filename_template)
+        .tag_extensionless(config.tag_extensionless)
+        .fix_extensions(config.fix_extensions)
+        .audio_format_priority(config.audio_format_priority)
+        .ebook_format_priority(config.ebook_format_priority)
+        .size_preference(config.size_preference)
+        .allowed_extensions(config.allowed_extensions)
+        .blocked_extensions(config.blocked_extensions)
+        .workers(workers)
+        .preallocate(config.preallocate)
+        .have(have)
+        .skip_complete(matches!(
+            opts.subcmd,
+            SubCommand::DownloadAll {
+                skip_complete: true,
+                ..
+            } | SubCommand::Watch { .. }
+        ))
+        .incremental(matches!(
+            opts.subcmd,
+            SubCommand::DownloadAll {
+                incremental: true,
+                ..
+            }
+        ))
+        .quarantine_on_failure(config.quarantine_on_failure)
+        .download_connections(config.download_connections)
+        .max_connections_per_host(config.max_connections_per_host)
+        .all_platforms(opts.all_platforms)
+        .platform_chain(opts.platform.into_iter().chain(opts.fallback).collect())
+        .keep_all_platforms(config.keep_all_platforms)
+        .post_download_command(config.post_download_command)
+        .categories(opts.category)
+        .retry(RetryPolicy {
+            retries: config.retries,
+            base_delay: Duration::from_millis(config.retry_delay_ms),
+        })
+        .retry_budget(config.retry_budget)
+        .check_file_size(config.check_file_size)
+        .quick_verify(opts.quick)
+        .verify(!opts.no_verify)
+        .verify_all_hashes(config.verify_all_hashes)
+        .byte_budget(opts.max_total_bytes)
+        .verify_checkpoint(match config.verify_checkpoint_hours {
+            0 => None,
+            hours => Some(Duration::from_secs(hours * 60 * 60)),
+        });
+
+    let client = match &session {
+        Some(session) => {
+            let previous = previous_session(&path, session);
+            client.session_folder(session, previous.as_deref())
+        }
+        None => client,
+    };
+
+    // a log gets a line for each file, the terminal only the overall one
+    let (client, files) = if io::stdout().is_terminal() {
+        (client, None)
+    } else {
+        let (events, receiver) = mpsc::channel(EVENT_CAPACITY);
+        (client.progress_events(events), Some(track_files(receiver)))
+    };
+
+    if opts.no_verify {
+        eprintln!("warning: --no-verify only checks file sizes, file integrity isn't guaranteed");
+    }
+
+    let cancelled = client.cancel_flag();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            println!("interrupted, stopping (ctrl-c again to quit now)");
+            cancelled.store(true, Ordering::SeqCst);
+        }
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            process::exit(EXIT_CANCELLED);
+        }
+    });
+
+    #[cfg(unix)]
+    handle_pause_signals(client.pause_flag())?;
 
     match opts.subcmd {
         SubCommand::ListOrders => {
@@ -40,22 +443,315 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             println!("{:?}", order_items);
         }
-        SubCommand::DownloadAll => {
-            let order_items = client.list_orders().await?;
+        SubCommand::DownloadAll { newest_only, .. } => {
+            report_progress(client.progress(), files);
+
+            let started = Instant::now();
+            let result = sync::download_all(
+                &client,
+                newest_only,
+                config.order_concurrency.max(1),
+                opts.json_errors,
+            )
+            .await;
+
+            let summary =
+                notify::Summary::new(&result, client.progress().downloaded(), started.elapsed());
+            notify::send(
+                config.notify_command.as_deref(),
+                config.notify_webhook.as_deref(),
+                &summary,
+            )
+            .await;
+
+            if opts.platform_report {
+                platform_report::print(&client.progress());
+            }
+
+            let stats = match result {
+                Err(ApiError::ByteBudgetReached(budget)) => {
+                    return stopped_at_budget(&client, budget);
+                }
+                result => result?,
+            };
+
+            if stats.failed > 0 {
+                return Err(PartialFailure {
+                    failed: stats.failed,
+                }
+                .into());
+            }
+
+            client.record_sync();
+        }
+        SubCommand::Watch { interval, .. } => {
+            report_progress(client.progress(), files);
 
-            for order_item in order_items {
-                println!("downloading order {}", &order_item.gamekey);
+            let interval = Duration::from_secs(interval.max(1) * 60);
+            let result = watch::run(
+                &client,
+                interval,
+                config.order_concurrency.max(1),
+                config.notify_command.as_deref(),
+                config.notify_webhook.as_deref(),
+                opts.json_errors,
+            )
+            .await;
 
-                let order = client.get_order(&order_item.gamekey).await?;
-                client.download_order(&order).await?;
+            match result {
+                Err(ApiError::ByteBudgetReached(budget)) => {
+                    return stopped_at_budget(&client, budget);
+                }
+                result => result?,
             }
         }
-        SubCommand::DownloadOrder { gamekey } => {
+        SubCommand::DownloadOrder {
+            gamekey,
+            subproduct,
+            ..
+        } => {
+            report_progress(client.progress(), files);
+
             let order = client.get_order(&gamekey).await?;
             println!("{:?}", order);
-            client.download_order(&order).await?;
+
+            let result = match subproduct {
+                Some(name) => {
+                    if !order.subproducts.iter().any(|s| s.is_named(&name)) {
+                        return Err(format!("order {} has no title named {}", gamekey, name).into());
+                    }
+
+                    client.download_order_subproduct(&order, &name).await
+                }
+                None => client.download_order(&order).await,
+            };
+
+            if opts.platform_report {
+                platform_report::print(&client.progress());
+            }
+
+            match result {
+                Err(ApiError::ByteBudgetReached(budget)) => {
+                    return stopped_at_budget(&client, budget);
+                }
+                result => result?,
+            }
+        }
+        SubCommand::RawOrder { gamekey } => {
+            let raw = client.get_order_raw(&gamekey).await?;
+
+            // pretty print when it parses, but never hide what was actually sent
+            match serde_json::from_str::<serde_json::Value>(&raw) {
+                Ok(json) => println!("{}", serde_json::to_string_pretty(&json)?),
+                Err(_) => println!("{}", raw),
+            }
         }
+        SubCommand::ShowOrder { gamekey } => show::run(&client, &gamekey).await?,
+        SubCommand::DownloadUrl { url } => client.download_url(&url).await?,
+        SubCommand::Export { format, out } => export::run(&client, format, &out).await?,
+        SubCommand::Audit { out } => audit::run(&client, &out).await?,
+        SubCommand::PrintUrls { gamekey, aria2 } => {
+            print_urls::run(&client, gamekey.as_deref(), aria2).await?
+        }
+        SubCommand::Reconcile => reconcile::run(&client).await?,
+        SubCommand::VerifyPath {
+            gamekey,
+            path,
+            repair,
+        } => verify::run(&client, &gamekey, &path, repair).await?,
+        SubCommand::Doctor | SubCommand::Login => unreachable!(),
     }
 
     Ok(())
 }
+
+/// Reads a have list, taking both halves of `<hash>  <file name>` lines as
+/// written by sha1sum and md5sum. Blank lines and `#` comments are ignored.
+fn read_have_list(path: &Path) -> Result<HashSet<String>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("couldn't read have list {}: {}", path.display(), e))?;
+
+    let mut have = HashSet::new();
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_once(char::is_whitespace) {
+            Some((hash, name)) if hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                have.insert(hash.to_lowercase());
+                // sha1sum marks files read in binary mode with a *
+                have.insert(name.trim_start().trim_start_matches('*').to_owned());
+            }
+            _ if line.chars().all(|c| c.is_ascii_hexdigit()) => {
+                have.insert(line.to_lowercase());
+            }
+            _ => {
+                have.insert(line.to_owned());
+            }
+        }
+    }
+
+    Ok(have)
+}
+
+/// The latest session folder in `folder` from before `session`, which
+/// unchanged files are linked from.
+fn previous_session(folder: &Path, session: &str) -> Option<String> {
+    fs::read_dir(folder)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| is_session_date(name) && name.as_str() < session)
+        .max()
+}
+
+fn is_session_date(name: &str) -> bool {
+    name.len() == 10
+        && name.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// The UTC date as `YYYY-MM-DD`, naming the folder a session downloads into.
+fn session_date(now: SystemTime) -> String {
+    let days = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86_400;
+
+    // civil date from days since 1970-01-01, proleptic gregorian calendar
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn stopped_at_budget(client: &HBClient, budget: u64) -> Result<(), Box<dyn Error>> {
+    println!(
+        "stopping, downloaded {} MB of the {} MB allowed for this run",
+        client.progress().downloaded() / MB,
+        budget / MB
+    );
+
+    Ok(())
+}
+
+/// SIGUSR1 pauses downloads and SIGUSR2 resumes them, e.g. to free up
+/// bandwidth for a while without killing a long sync.
+#[cfg(unix)]
+fn handle_pause_signals(paused: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut pause = signal(SignalKind::user_defined1())?;
+    let mut resume = signal(SignalKind::user_defined2())?;
+
+    let pause_flag = paused.clone();
+    tokio::spawn(async move {
+        while pause.recv().await.is_some() {
+            println!("pausing downloads (SIGUSR2 to resume)");
+            pause_flag.store(true, Ordering::SeqCst);
+        }
+    });
+
+    tokio::spawn(async move {
+        while resume.recv().await.is_some() {
+            println!("resuming downloads");
+            paused.store(false, Ordering::SeqCst);
+        }
+    });
+
+    Ok(())
+}
+
+/// Files downloading right now, by gamekey and name, with the bytes done so
+/// far and the size if it's known.
+type InFlight = Arc<Mutex<BTreeMap<(Option<String>, String), (u64, Option<u64>)>>>;
+
+/// Keeps track of the files downloading from the client's progress events.
+fn track_files(mut events: Receiver<ProgressEvent>) -> InFlight {
+    let files = InFlight::default();
+
+    let tracked = files.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let mut files = tracked.lock().unwrap();
+
+            match event {
+                ProgressEvent::Started {
+                    gamekey,
+                    file,
+                    size,
+                } => {
+                    files.insert((gamekey, file), (0, size));
+                }
+                ProgressEvent::Progress {
+                    gamekey,
+                    file,
+                    bytes,
+                } => {
+                    if let Some((done, _)) = files.get_mut(&(gamekey, file)) {
+                        *done += bytes;
+                    }
+                }
+                ProgressEvent::Done { gamekey, file, .. }
+                | ProgressEvent::Error { gamekey, file, .. } => {
+                    files.remove(&(gamekey, file));
+                }
+            }
+        }
+    });
+
+    files
+}
+
+fn report_progress(progress: Arc<Progress>, files: Option<InFlight>) {
+    let period = match files {
+        None => PROGRESS_INTERVAL,
+        Some(_) => LOG_PROGRESS_INTERVAL,
+    };
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        let mut last_downloaded = 0;
+
+        loop {
+            interval.tick().await;
+
+            if let Some(files) = &files {
+                for ((_, file), (done, size)) in files.lock().unwrap().iter() {
+                    match size {
+                        Some(size) if *size > 0 => println!(
+                            "downloading {}: {}% ({}/{} MB)",
+                            file,
+                            done * 100 / size,
+                            done / MB,
+                            size / MB
+                        ),
+                        _ => println!("downloading {}: {} MB", file, done / MB),
+                    }
+                }
+            }
+
+            let downloaded = progress.downloaded();
+            let total = progress.total();
+
+            if total > 0 && downloaded != last_downloaded {
+                println!(
+                    "overall progress: {}% ({}/{} MB)",
+                    downloaded * 100 / total,
+                    downloaded / MB,
+                    total / MB
+                );
+                last_downloaded = downloaded;
+            }
+        }
+    });
+}
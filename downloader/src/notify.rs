@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::process::Command;
+
+use crate::describe;
+
+/// What gets reported to the completion hooks at the end of a sync.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    /// `success`, `partial`, `stopped` (at --max-total-bytes) or `failed`
+    pub status: &'static str,
+    pub orders: usize,
+    pub failed_orders: usize,
+    pub downloaded_bytes: u64,
+    pub duration_secs: u64,
+    pub error: Option<String>,
+}
+
+impl Summary {
+    pub fn new(
+        result: &Result<crate::sync::SyncStats, hb_api::ApiError>,
+        downloaded_bytes: u64,
+        duration: Duration,
+    ) -> Self {
+        let (status, orders, failed_orders, error) = match result {
+            Ok(stats) if stats.failed == 0 => ("success", stats.orders, 0, None),
+            Ok(stats) => ("partial", stats.orders, stats.failed, None),
+            Err(e @ hb_api::ApiError::ByteBudgetReached(_)) => ("stopped", 0, 0, Some(describe(e))),
+            Err(e) => ("failed", 0, 0, Some(describe(e))),
+        };
+
+        Self {
+            status,
+            orders,
+            failed_orders,
+            downloaded_bytes,
+            duration_secs: duration.as_secs(),
+            error,
+        }
+    }
+}
+
+/// Runs the configured completion command and posts to the configured
+/// webhook. Failures are only warned about, they never fail the sync itself.
+pub async fn send(command: Option<&str>, webhook: Option<&str>, summary: &Summary) {
+    if let Some(command) = command {
+        if let Err(e) = run_command(command, summary).await {
+            eprintln!("warning: notify command failed: {}", e);
+        }
+    }
+
+    if let Some(webhook) = webhook {
+        if let Err(e) = post_webhook(webhook, summary).await {
+            eprintln!("warning: notify webhook failed: {}", describe(&e));
+        }
+    }
+}
+
+async fn run_command(command: &str, summary: &Summary) -> Result<(), String> {
+    let status = Command::from(hb_api::shell(command))
+        .env("HB_STATUS", summary.status)
+        .env("HB_ORDERS", summary.orders.to_string())
+        .env("HB_FAILED_ORDERS", summary.failed_orders.to_string())
+        .env("HB_DOWNLOADED_BYTES", summary.downloaded_bytes.to_string())
+        .env("HB_DURATION_SECS", summary.duration_secs.to_string())
+        .env("HB_ERROR", summary.error.as_deref().unwrap_or(""))
+        .status()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("exited with {}", status))
+    }
+}
+
+async fn post_webhook(webhook: &str, summary: &Summary) -> Result<(), reqwest::Error> {
+    reqwest::Client::new()
+        .post(webhook)
+        .json(summary)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
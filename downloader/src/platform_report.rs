@@ -0,0 +1,38 @@
+use hb_api::Progress;
+
+use crate::show::format_size;
+
+/// Prints how many files and bytes each platform contributed to this run.
+pub fn print(progress: &Progress) {
+    let platforms = progress.by_platform();
+
+    if platforms.is_empty() {
+        println!("no files downloaded");
+        return;
+    }
+
+    let width = platforms
+        .iter()
+        .map(|(platform, _)| platform.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("PLATFORM".len());
+
+    println!(
+        "{:<w$}  {:>6}  {:>10}",
+        "PLATFORM",
+        "FILES",
+        "SIZE",
+        w = width
+    );
+
+    for (platform, totals) in platforms {
+        println!(
+            "{:<w$}  {:>6}  {:>10}",
+            platform,
+            totals.files,
+            format_size(totals.bytes),
+            w = width
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::error::Error;
+
+use hb_api::HBClient;
+
+/// Prints the signed download urls of the files a download would fetch, from
+/// one order or every order, for handing to another downloader. With `aria2`
+/// each url comes with the folder and name it would be saved as and its
+/// checksum, in aria2c's `--input-file` format.
+pub async fn run(
+    client: &HBClient,
+    gamekey: Option<&str>,
+    aria2: bool,
+) -> Result<(), Box<dyn Error>> {
+    // signed urls expire, so cached orders won't do
+    client.clear_order_cache();
+
+    let gamekeys = match gamekey {
+        Some(gamekey) => vec![gamekey.to_owned()],
+        None => client
+            .list_orders()
+            .await?
+            .into_iter()
+            .map(|order_item| order_item.gamekey)
+            .collect(),
+    };
+
+    for gamekey in &gamekeys {
+        let order = client.get_order(gamekey).await?;
+
+        for (path, file) in client.planned_files(&order) {
+            let url = match &file.url {
+                Some(url) => &url.web,
+                None => continue,
+            };
+
+            println!("{}", url);
+
+            if !aria2 {
+                continue;
+            }
+
+            if let (Some(dir), Some(out)) = (path.parent(), path.file_name()) {
+                println!("  dir={}", dir.display());
+                println!("  out={}", out.to_string_lossy());
+            }
+
+            if let Some(sha1) = &file.sha1 {
+                println!("  checksum=sha-1={}", sha1);
+            } else if let Some(md5) = &file.md5 {
+                println!("  checksum=md5={}", md5);
+            }
+        }
+    }
+
+    Ok(())
+}
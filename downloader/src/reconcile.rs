@@ -0,0 +1,118 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use hb_api::{HBClient, OBJECTS_FOLDER, QUARANTINE_FOLDER};
+
+/// Compares what the library says should be in the download folder and the
+/// platform folders with what is actually there, without downloading
+/// anything.
+pub async fn run(client: &HBClient) -> Result<(), Box<dyn Error>> {
+    let mut expected = Vec::new();
+
+    for order_item in client.list_orders().await? {
+        let order = client.get_order(&order_item.gamekey).await?;
+
+        for (path, file) in client.planned_files(&order) {
+            expected.push((path, file.clone()));
+        }
+    }
+
+    let mut valid = 0;
+    let mut missing = Vec::new();
+    let mut stale = Vec::new();
+
+    for (path, file) in &expected {
+        if !path.exists() {
+            missing.push(path);
+        } else if client.check_existing(file, path).await? {
+            valid += 1;
+        } else {
+            stale.push(path);
+        }
+    }
+
+    let expected: HashSet<_> = expected.iter().map(|(path, _)| path.clone()).collect();
+
+    let roots = client.download_roots();
+
+    let mut on_disk = Vec::new();
+    for root in &roots {
+        // a platform folder inside another root is listed along with it
+        let nested = roots
+            .iter()
+            .any(|other| other != root && root.starts_with(other));
+
+        if root.is_dir() && !nested {
+            list_files(root, &mut on_disk)?;
+        }
+    }
+
+    let mut extra: Vec<_> = on_disk
+        .into_iter()
+        .filter(|path| !expected.contains(path) && !is_own_file(&roots, path))
+        .collect();
+    extra.sort();
+
+    print_section("missing locally", &missing);
+    print_section("stale or corrupt", &stale);
+    print_section("not in the library", &extra);
+
+    println!(
+        "{} present and valid, {} missing, {} stale, {} extra",
+        valid,
+        missing.len(),
+        stale.len(),
+        extra.len()
+    );
+
+    Ok(())
+}
+
+fn print_section(heading: &str, paths: &[impl AsRef<Path>]) {
+    if paths.is_empty() {
+        return;
+    }
+
+    println!("{}:", heading);
+
+    for path in paths {
+        println!("  {}", path.as_ref().display());
+    }
+}
+
+fn list_files(folder: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// The tool's own state, config, partial downloads and their state,
+/// quarantined files and content-addressed objects, which aren't library
+/// files under their own paths.
+fn is_own_file(roots: &[PathBuf], path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+
+    name.starts_with(".hb-downloader")
+        || name.ends_with(".part")
+        || name.contains(".part.")
+        || roots.iter().any(|root| {
+            path == root.join("config.toml")
+                || path.starts_with(root.join(QUARANTINE_FOLDER))
+                || path.starts_with(root.join(OBJECTS_FOLDER))
+        })
+}
@@ -0,0 +1,101 @@
+use hb_api::{types::Order, ApiError, HBClient};
+
+use crate::MB;
+
+const HEADINGS: [&str; 4] = ["SUBPRODUCT", "PLATFORM", "FILE", "SIZE"];
+
+pub async fn run(client: &HBClient, gamekey: &str) -> Result<(), ApiError> {
+    let order = client.get_order(gamekey).await?;
+
+    println!("{} ({})", order.product.human_name, order.gamekey);
+    print_table(&rows(&order));
+
+    for platform in platforms(&order) {
+        let total = order
+            .total_size(std::slice::from_ref(&platform))
+            .map(format_size)
+            .unwrap_or_else(|| "unknown, humble didn't list every size".to_owned());
+
+        println!("{} total: {}", platform, total);
+    }
+
+    Ok(())
+}
+
+/// The order's platforms, in the order they first appear.
+fn platforms(order: &Order) -> Vec<String> {
+    let mut platforms = Vec::new();
+
+    for download in order
+        .subproducts
+        .iter()
+        .flat_map(|subproduct| &subproduct.downloads)
+    {
+        if !platforms.contains(&download.platform) {
+            platforms.push(download.platform.clone());
+        }
+    }
+
+    platforms
+}
+
+fn rows(order: &Order) -> Vec<[String; 4]> {
+    let mut rows = Vec::new();
+
+    for subproduct in &order.subproducts {
+        for download in &subproduct.downloads {
+            for file in &download.download_struct {
+                rows.push([
+                    subproduct.human_name.clone(),
+                    download.platform.clone(),
+                    file.file_name()
+                        .or_else(|| file.name.clone())
+                        .unwrap_or_else(|| "-".to_owned()),
+                    file.file_size
+                        .map(format_size)
+                        .unwrap_or_else(|| "-".to_owned()),
+                ]);
+            }
+        }
+    }
+
+    rows
+}
+
+fn print_table(rows: &[[String; 4]]) {
+    let mut widths = HEADINGS.map(str::len);
+
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: [&str; 4]| {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}",
+            cells[0],
+            cells[1],
+            cells[2],
+            cells[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    };
+
+    print_row(HEADINGS);
+
+    for row in rows {
+        print_row([&row[0], &row[1], &row[2], &row[3]]);
+    }
+}
+
+pub(crate) fn format_size(size: u64) -> String {
+    if size >= MB {
+        format!("{:.1} MB", size as f64 / MB as f64)
+    } else {
+        format!("{:.1} KB", size as f64 / 1024.0)
+    }
+}
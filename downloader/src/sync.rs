@@ -0,0 +1,179 @@
+use std::fmt;
+
+use futures::{stream, StreamExt};
+use hb_api::{ApiError, HBClient};
+
+use crate::report_error;
+
+/// How a `DownloadAll` run went, order by order.
+#[derive(Debug, Default)]
+pub struct SyncStats {
+    pub orders: usize,
+    pub failed: usize,
+}
+
+impl fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} orders, {} failed", self.orders, self.failed)
+    }
+}
+
+pub async fn download_all(
+    client: &HBClient,
+    newest_only: bool,
+    order_concurrency: usize,
+    json_errors: bool,
+) -> Result<SyncStats, ApiError> {
+    let order_items = client.list_orders().await?;
+    let mut stats = SyncStats {
+        orders: order_items.len(),
+        ..Default::default()
+    };
+
+    if newest_only {
+        let mut orders = Vec::new();
+        let mut fetches = stream::iter(&order_items)
+            .map(|order_item| async move {
+                (
+                    &order_item.gamekey,
+                    client.get_order(&order_item.gamekey).await,
+                )
+            })
+            .buffer_unordered(order_concurrency);
+
+        while let Some((gamekey, order)) = fetches.next().await {
+            if let Some(order) = tolerate(order, gamekey, &mut stats.failed, json_errors)? {
+                orders.push(order);
+            }
+        }
+
+        let skipped = hb_api::keep_newest_subproducts(&mut orders);
+        println!("skipping {} titles already in newer orders", skipped);
+
+        let mut downloads = stream::iter(&orders)
+            .map(|order| async move {
+                println!("downloading order {}", &order.gamekey);
+                (&order.gamekey, client.download_order(order).await)
+            })
+            .buffer_unordered(order_concurrency);
+
+        while let Some((gamekey, result)) = downloads.next().await {
+            tolerate(result, gamekey, &mut stats.failed, json_errors)?;
+        }
+    } else {
+        let mut downloads = stream::iter(&order_items)
+            .map(|order_item| async move {
+                println!("downloading order {}", &order_item.gamekey);
+                (
+                    &order_item.gamekey,
+                    download_order(client, &order_item.gamekey).await,
+                )
+            })
+            .buffer_unordered(order_concurrency);
+
+        while let Some((gamekey, result)) = downloads.next().await {
+            tolerate(result, gamekey, &mut stats.failed, json_errors)?;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Logs and counts an order's failure so the run can carry on with the next
+/// one, unless it's a failure that should stop the whole run.
+fn tolerate<T>(
+    result: Result<T, ApiError>,
+    gamekey: &str,
+    failed: &mut usize,
+    json_errors: bool,
+) -> Result<Option<T>, ApiError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e @ ApiError::Cancelled)
+        | Err(e @ ApiError::Unauthorized)
+        | Err(e @ ApiError::ByteBudgetReached(_))
+        | Err(e @ ApiError::DiskFull(_))
+        | Err(e @ ApiError::RetryBudgetExhausted(_)) => Err(e),
+        // nothing left to download, so not a failure
+        Err(e @ ApiError::OrderNotFound { .. }) => {
+            println!("skipping order: {}", e);
+            Ok(None)
+        }
+        Err(e) => {
+            report_error(json_errors, Some(gamekey), &e);
+            *failed += 1;
+            Ok(None)
+        }
+    }
+}
+
+async fn download_order(client: &HBClient, gamekey: &str) -> Result<(), ApiError> {
+    let order = client.get_order(gamekey).await?;
+    client.download_order(&order).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use hb_api::RetryPolicy;
+    use hb_test_support::{MockServer, Route};
+
+    use super::*;
+
+    /// A humble with a refunded order, which 404s, and an empty one.
+    fn humble() -> (MockServer, HBClient) {
+        let server = MockServer::start();
+        server.route(
+            "/api/v1/user/order",
+            Route::ok(r#"[{"gamekey": "refunded"}, {"gamekey": "kept"}]"#),
+        );
+        server.route(
+            "/api/v1/order/kept",
+            Route::ok(
+                r#"{"gamekey": "kept", "product": {"human_name": "Kept"}, "subproducts": []}"#,
+            ),
+        );
+
+        let client = HBClient::with_client(reqwest::Client::new(), env::temp_dir(), Vec::new())
+            .base_url(&format!("{}/api/v1", server.url()))
+            .retry(RetryPolicy {
+                retries: 0,
+                ..Default::default()
+            });
+
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn skips_orders_that_arent_found() {
+        let (server, client) = humble();
+
+        let stats = download_all(&client, false, 1, false).await.unwrap();
+
+        assert_eq!(stats.orders, 2);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(server.hits("/api/v1/order/refunded"), 1);
+        assert_eq!(server.hits("/api/v1/order/kept"), 1);
+    }
+
+    #[tokio::test]
+    async fn skips_orders_that_arent_found_when_keeping_the_newest() {
+        let (server, client) = humble();
+
+        let stats = download_all(&client, true, 1, false).await.unwrap();
+
+        assert_eq!(stats.failed, 0);
+        assert_eq!(server.hits("/api/v1/order/kept"), 1);
+    }
+
+    #[tokio::test]
+    async fn counts_other_order_failures() {
+        let (server, client) = humble();
+        server.route("/api/v1/order/kept", Route::status(500));
+
+        let stats = download_all(&client, false, 1, false).await.unwrap();
+
+        assert_eq!(stats.failed, 1);
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use hb_api::{types::Order, ApiError, HBClient};
+
+#[derive(Debug)]
+pub struct VerificationFailed {
+    failed: usize,
+}
+
+impl fmt::Display for VerificationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files failed verification", self.failed)
+    }
+}
+
+impl Error for VerificationFailed {}
+
+/// How the files of an order compared with what's under a folder.
+#[derive(Debug)]
+struct Tally<'a> {
+    path: &'a Path,
+    repair: bool,
+    passed: usize,
+    repaired: usize,
+    failed: usize,
+    missing: usize,
+}
+
+impl fmt::Display for Tally<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.repair {
+            write!(
+                f,
+                "{} ok, {} repaired, {} still mismatched, {} not found under {}",
+                self.passed,
+                self.repaired,
+                self.failed,
+                self.missing,
+                self.path.display()
+            )
+        } else {
+            write!(
+                f,
+                "{} ok, {} mismatched, {} not found under {}",
+                self.passed,
+                self.failed,
+                self.missing,
+                self.path.display()
+            )
+        }
+    }
+}
+
+/// Checks the files under `path` against an order's hashes, matching them up
+/// by file name. With `repair`, files that fail are downloaded again in place,
+/// otherwise nothing is downloaded.
+pub async fn run(
+    client: &HBClient,
+    gamekey: &str,
+    path: &Path,
+    repair: bool,
+) -> Result<(), Box<dyn Error>> {
+    let order = client.get_order(gamekey).await?;
+
+    let mut local = HashMap::new();
+    index_files(path, &mut local)?;
+
+    let mut tally = Tally {
+        path,
+        repair,
+        passed: 0,
+        repaired: 0,
+        failed: 0,
+        missing: 0,
+    };
+
+    if let Err(e) = check_files(client, &order, &local, &mut tally).await {
+        if let ApiError::Cancelled = e {
+            println!("interrupted, checked so far: {}", tally);
+        }
+
+        return Err(e.into());
+    }
+
+    println!("{}", tally);
+
+    if tally.failed > 0 {
+        return Err(VerificationFailed {
+            failed: tally.failed,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Checks each of the order's files, counting into `tally` as it goes so an
+/// interrupted check can still report what it got through.
+async fn check_files(
+    client: &HBClient,
+    order: &Order,
+    local: &HashMap<String, PathBuf>,
+    tally: &mut Tally<'_>,
+) -> Result<(), ApiError> {
+    for subproduct in &order.subproducts {
+        for download in &subproduct.downloads {
+            for file in &download.download_struct {
+                let name = match file.file_name() {
+                    Some(name) => name,
+                    None => continue,
+                };
+
+                match local.get(&name) {
+                    Some(local_path) if client.verify_file(file, local_path).await? => {
+                        println!("OK       {}", local_path.display());
+                        tally.passed += 1;
+                    }
+                    Some(local_path) if tally.repair => {
+                        if client.redownload(download, file, local_path).await? {
+                            println!("REPAIRED {}", local_path.display());
+                            tally.repaired += 1;
+                        } else {
+                            println!("MISMATCH {}", local_path.display());
+                            tally.failed += 1;
+                        }
+                    }
+                    Some(local_path) => {
+                        println!("MISMATCH {}", local_path.display());
+                        tally.failed += 1;
+                    }
+                    None => {
+                        println!("MISSING  {}", name);
+                        tally.missing += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Every file under `folder` by name. Where names repeat, the first one found
+/// is checked.
+fn index_files(folder: &Path, files: &mut HashMap<String, PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            index_files(&path, files)?;
+        } else if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            files.entry(name.to_owned()).or_insert(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use hb_api::RetryPolicy;
+    use hb_test_support::{MockServer, Route};
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+
+    const GOOD: &[u8] = b"the notes as humble lists them";
+    const BAD: &[u8] = b"the notes with a flipped byte!";
+
+    /// An order of one file, `notes.txt`, and a client for it downloading
+    /// into `folder`.
+    fn humble(folder: &Path) -> (MockServer, Order, HBClient) {
+        let server = MockServer::start();
+
+        let order = serde_json::from_value(serde_json::json!({
+            "gamekey": "notes",
+            "product": {"human_name": "Notes"},
+            "subproducts": [{
+                "human_name": "Notes",
+                "downloads": [{
+                    "platform": "ebook",
+                    "download_struct": [{
+                        "sha1": format!("{:x}", Sha1::digest(GOOD)),
+                        "file_size": GOOD.len(),
+                        "url": {"web": format!("{}/files/notes.txt", server.url())},
+                    }],
+                }],
+            }],
+        }))
+        .unwrap();
+
+        let client = HBClient::with_client(
+            reqwest::Client::new(),
+            folder.to_path_buf(),
+            vec!["ebook".to_owned()],
+        )
+        .retry(RetryPolicy {
+            retries: 0,
+            ..Default::default()
+        });
+
+        (server, order, client)
+    }
+
+    #[tokio::test]
+    async fn repairs_only_count_when_the_new_copy_checks_out() {
+        let folder = env::temp_dir().join(format!("hb-downloader-verify-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("notes.txt");
+        fs::write(&path, BAD).unwrap();
+
+        let (server, order, client) = humble(&folder);
+        let mut local = HashMap::new();
+        local.insert("notes.txt".to_owned(), path.clone());
+        let mut tally = Tally {
+            path: &folder,
+            repair: true,
+            passed: 0,
+            repaired: 0,
+            failed: 0,
+            missing: 0,
+        };
+
+        // humble's copy is broken too, so the download fails its hash
+        server.route("/files/notes.txt", Route::ok(BAD));
+        check_files(&client, &order, &local, &mut tally)
+            .await
+            .unwrap();
+
+        assert_eq!((tally.repaired, tally.failed), (0, 1));
+        assert_eq!(fs::read(&path).unwrap(), BAD);
+
+        server.route("/files/notes.txt", Route::ok(GOOD));
+        check_files(&client, &order, &local, &mut tally)
+            .await
+            .unwrap();
+
+        assert_eq!((tally.repaired, tally.failed), (1, 1));
+        assert_eq!(fs::read(&path).unwrap(), GOOD);
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use hb_api::{ApiError, HBClient};
+use tokio::time;
+
+use crate::{notify, report_error, sync, MB};
+
+// how often the wait between syncs checks for ctrl-c
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs `DownloadAll` every `interval` until cancelled. Orders that completed
+/// on an earlier cycle are skipped while they're unchanged, and a failed
+/// cycle is only reported, the next one tries again.
+pub async fn run(
+    client: &HBClient,
+    interval: Duration,
+    order_concurrency: usize,
+    notify_command: Option<&str>,
+    notify_webhook: Option<&str>,
+    json_errors: bool,
+) -> Result<(), ApiError> {
+    let cancelled = client.cancel_flag();
+
+    loop {
+        // new purchases and fresh download links only show up in new fetches
+        client.clear_order_cache();
+        // an outage that used up the last cycle's budget may be over by now
+        client.reset_retry_budget();
+
+        let started = Instant::now();
+        let downloaded_before = client.progress().downloaded();
+
+        let result = sync::download_all(client, false, order_concurrency, json_errors).await;
+
+        let summary = notify::Summary::new(
+            &result,
+            client.progress().downloaded() - downloaded_before,
+            started.elapsed(),
+        );
+        notify::send(notify_command, notify_webhook, &summary).await;
+
+        match result {
+            Ok(stats) => println!(
+                "sync finished: {}, {} MB downloaded in {}s",
+                stats,
+                summary.downloaded_bytes / MB,
+                summary.duration_secs
+            ),
+            // nothing the next cycle would do differently
+            Err(e @ ApiError::Cancelled)
+            | Err(e @ ApiError::Unauthorized)
+            | Err(e @ ApiError::ByteBudgetReached(_)) => return Err(e),
+            Err(e) => {
+                report_error(json_errors, None, &e);
+                println!("sync failed, trying again next time");
+            }
+        }
+
+        println!("next sync in {} min", interval.as_secs() / 60);
+
+        let wake = Instant::now() + interval;
+        while Instant::now() < wake {
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(ApiError::Cancelled);
+            }
+
+            time::delay_for(CANCEL_POLL_INTERVAL).await;
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! A small HTTP/1.1 server for tests, answering GETs from a table of routes on
+//! background threads. Only uses std, so it's cheap to depend on from any
+//! crate's tests and benchmarks.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+// generated bodies are written in pieces this size, never built up whole
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The byte at `offset` in a generated body.
+pub fn generated_byte(offset: u64) -> u8 {
+    (offset % 251) as u8
+}
+
+#[derive(Clone, Debug)]
+enum Body {
+    Bytes(Vec<u8>),
+    /// `len` bytes of `generated_byte`, made up as they're sent.
+    Generated(u64),
+}
+
+impl Body {
+    fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::Generated(len) => *len,
+        }
+    }
+}
+
+/// What the server answers a path with.
+#[derive(Clone, Debug)]
+pub struct Route {
+    status: u16,
+    body: Body,
+    accept_ranges: bool,
+    ignore_ranges: bool,
+    short_ranges_at: Option<u64>,
+    hang_up_at: Option<u64>,
+    location: Option<String>,
+}
+
+impl Route {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            body: Body::Bytes(body.into()),
+            accept_ranges: false,
+            ignore_ranges: false,
+            short_ranges_at: None,
+            hang_up_at: None,
+            location: None,
+        }
+    }
+
+    /// `len` bytes that aren't held in memory, see `generated_byte`.
+    pub fn generated(len: u64) -> Self {
+        Self {
+            status: 200,
+            body: Body::Generated(len),
+            accept_ranges: false,
+            ignore_ranges: false,
+            short_ranges_at: None,
+            hang_up_at: None,
+            location: None,
+        }
+    }
+
+    /// An empty response with this status, e.g. 404.
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            body: Body::Bytes(Vec::new()),
+            accept_ranges: false,
+            ignore_ranges: false,
+            short_ranges_at: None,
+            hang_up_at: None,
+            location: None,
+        }
+    }
+
+    /// A 302 to `location`, like humble's download links sending the client
+    /// on to their cdn.
+    pub fn redirect(location: impl Into<String>) -> Self {
+        Self {
+            location: Some(location.into()),
+            ..Self::status(302)
+        }
+    }
+
+    /// Advertise and honour `Range: bytes=<start>-[<end>]` requests.
+    pub fn accept_ranges(mut self) -> Self {
+        self.accept_ranges = true;
+        self
+    }
+
+    /// Advertise byte ranges, but answer every request with the whole body,
+    /// like a server that doesn't really support them.
+    pub fn ignore_ranges(mut self) -> Self {
+        self.accept_ranges = true;
+        self.ignore_ranges = true;
+        self
+    }
+
+    /// End any byte range that covers `offset` there, with headers that say
+    /// so, like a server sending less of a range than was asked for.
+    pub fn short_ranges_at(mut self, offset: u64) -> Self {
+        self.short_ranges_at = Some(offset);
+        self
+    }
+
+    /// Close the connection once the body reaches `offset`, without sending
+    /// the rest the headers promised, like a dropped connection.
+    pub fn hang_up_at(mut self, offset: u64) -> Self {
+        self.hang_up_at = Some(offset);
+        self
+    }
+}
+
+/// A request the server received, without its query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub path: String,
+    pub range: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    routes: Mutex<HashMap<String, Route>>,
+    requests: Mutex<Vec<Request>>,
+    connections: AtomicUsize,
+    in_flight: AtomicUsize,
+    most_in_flight: AtomicUsize,
+    ranges_in_flight: AtomicUsize,
+    most_ranges_in_flight: AtomicUsize,
+}
+
+pub struct MockServer {
+    address: SocketAddr,
+    state: Arc<State>,
+}
+
+impl MockServer {
+    /// Listens on a free port on localhost until the process exits.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(State::default());
+
+        let accepting = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+
+                accepting.connections.fetch_add(1, Ordering::SeqCst);
+                // the head and body go out in separate writes, which nagle
+                // would hold back for the client's delayed ack
+                let _ = stream.set_nodelay(true);
+
+                let state = accepting.clone();
+                thread::spawn(move || {
+                    // the client hanging up is the usual way a connection ends
+                    let _ = serve(stream, &state);
+                });
+            }
+        });
+
+        Self { address, state }
+    }
+
+    /// e.g. `http://127.0.0.1:41234`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn route(&self, path: &str, route: Route) {
+        self.state
+            .routes
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), route);
+    }
+
+    /// Every request for `path` so far, in the order they came in.
+    pub fn requests(&self, path: &str) -> Vec<Request> {
+        self.state
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| request.path == path)
+            .cloned()
+            .collect()
+    }
+
+    pub fn hits(&self, path: &str) -> usize {
+        self.requests(path).len()
+    }
+
+    /// TCP connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+
+    /// The most requests that were being answered at the same time.
+    pub fn most_in_flight(&self) -> usize {
+        self.state.most_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Like `most_in_flight`, counting only requests with a Range header.
+    pub fn most_ranges_in_flight(&self) -> usize {
+        self.state.most_ranges_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// Answers requests on one connection until the client closes it.
+fn serve(stream: TcpStream, state: &State) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+
+        let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let path = target.split('?').next().unwrap_or(target).to_owned();
+
+        let mut range = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 {
+                return Ok(());
+            }
+
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("range") {
+                    range = Some(value.trim().to_owned());
+                }
+            }
+        }
+
+        state.requests.lock().unwrap().push(Request {
+            path: path.clone(),
+            range: range.clone(),
+        });
+
+        let route = state
+            .routes
+            .lock()
+            .unwrap()
+            .get(&path)
+            .cloned()
+            .unwrap_or_else(|| Route::status(404));
+
+        let counters = [
+            Some((&state.in_flight, &state.most_in_flight)),
+            range
+                .as_ref()
+                .map(|_| (&state.ranges_in_flight, &state.most_ranges_in_flight)),
+        ];
+
+        for (in_flight, most) in counters.iter().flatten() {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+        }
+
+        let result = respond(&mut writer, &route, range.as_deref());
+
+        for (in_flight, _) in counters.iter().flatten() {
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+        result?;
+    }
+}
+
+fn respond(writer: &mut TcpStream, route: &Route, range: Option<&str>) -> io::Result<()> {
+    let len = route.body.len();
+
+    let (status, start, end) = match range.and_then(|range| parse_range(range, len)) {
+        Some((start, end))
+            if route.accept_ranges && !route.ignore_ranges && route.status == 200 =>
+        {
+            (206, start, end)
+        }
+        _ => (route.status, 0, len),
+    };
+
+    let end = match route.short_ranges_at {
+        Some(offset) if status == 206 && start < offset && offset < end => offset,
+        _ => end,
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+        status,
+        reason(status),
+        end - start
+    );
+    if let Some(location) = &route.location {
+        head.push_str(&format!("Location: {}\r\n", location));
+    }
+    if route.accept_ranges {
+        head.push_str("Accept-Ranges: bytes\r\n");
+    }
+    if status == 206 {
+        head.push_str(&format!(
+            "Content-Range: bytes {}-{}/{}\r\n",
+            start,
+            end - 1,
+            len
+        ));
+    }
+    head.push_str("\r\n");
+
+    writer.write_all(head.as_bytes())?;
+
+    let hang_up = route
+        .hang_up_at
+        .filter(|&offset| start <= offset && offset < end);
+    let end = hang_up.unwrap_or(end);
+
+    match &route.body {
+        Body::Bytes(bytes) => writer.write_all(&bytes[start as usize..end as usize])?,
+        Body::Generated(_) => {
+            let mut buffer = Vec::with_capacity(WRITE_BUFFER_SIZE);
+            let mut offset = start;
+
+            while offset < end {
+                buffer.clear();
+                let piece_end = end.min(offset + WRITE_BUFFER_SIZE as u64);
+                buffer.extend((offset..piece_end).map(generated_byte));
+                writer.write_all(&buffer)?;
+                offset = piece_end;
+            }
+        }
+    }
+
+    writer.flush()?;
+
+    match hang_up {
+        Some(_) => Err(io::Error::other("hung up")),
+        None => Ok(()),
+    }
+}
+
+/// `bytes=<start>-` or `bytes=<start>-<end>` as a half open range, if it's
+/// inside a body of `len` bytes.
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => len,
+        end => end.parse::<u64>().ok()?.saturating_add(1).min(len),
+    };
+
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        302 => "Found",
+        403 => "Forbidden",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}